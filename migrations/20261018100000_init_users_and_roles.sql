@@ -0,0 +1,33 @@
+-- Add migration script here
+CREATE TYPE role_enum AS ENUM ('admin', 'analyst', 'member');
+CREATE TYPE permission_enum AS ENUM ('devents:read_any', 'recordings:read_any', 'recordings:download', 'roles:manage');
+
+CREATE TABLE users (
+    id TEXT PRIMARY KEY, -- WorkOS user id
+    email TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    deleted_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE TABLE user_roles (
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role role_enum NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (user_id, role)
+);
+
+CREATE TABLE role_permissions (
+    role role_enum NOT NULL,
+    permission permission_enum NOT NULL,
+    PRIMARY KEY (role, permission)
+);
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('admin', 'devents:read_any'),
+    ('admin', 'recordings:read_any'),
+    ('admin', 'recordings:download'),
+    ('admin', 'roles:manage'),
+    ('analyst', 'devents:read_any'),
+    ('analyst', 'recordings:read_any'),
+    ('analyst', 'recordings:download');
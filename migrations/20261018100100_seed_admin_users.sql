@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Admins that used to be hardcoded in AuthenticatedUser::is_admin
+INSERT INTO users (id) VALUES
+    ('user_01HRBJ8FVP3JT28DEWXN6JPKF5'), -- Sulaiman skghori
+    ('user_01HY5EW9Z5XVE34GZXKH4NC2Y1'),
+    ('user_01J12R88378H1Z5R3JCGEPJ6RA')
+ON CONFLICT (id) DO NOTHING;
+
+INSERT INTO user_roles (user_id, role) VALUES
+    ('user_01HRBJ8FVP3JT28DEWXN6JPKF5', 'admin'),
+    ('user_01HY5EW9Z5XVE34GZXKH4NC2Y1', 'admin'),
+    ('user_01J12R88378H1Z5R3JCGEPJ6RA', 'admin')
+ON CONFLICT (user_id, role) DO NOTHING;
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- Rows written before this migration have no known owner and stay NULL
+ALTER TABLE devents ADD COLUMN user_id TEXT;
+ALTER TABLE recordings ADD COLUMN user_id TEXT;
+
+CREATE INDEX devents_session_id_idx ON devents (session_id);
+CREATE INDEX recordings_session_id_idx ON recordings (session_id);
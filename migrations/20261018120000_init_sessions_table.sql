@@ -0,0 +1,30 @@
+-- Add migration script here
+CREATE TABLE sessions (
+    id UUID PRIMARY KEY,
+    user_id TEXT, -- NULL only for sessions backfilled from rows that predate ownership
+    app_version TEXT,
+    os TEXT,
+    device_id TEXT,
+    screen_width INTEGER,
+    screen_height INTEGER,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    ended_at TIMESTAMP WITH TIME ZONE,
+    last_heartbeat_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    deleted_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id, started_at);
+
+-- Backfill the sessions that clients already invented, they are all over so mark them ended
+INSERT INTO sessions (id, user_id, started_at, ended_at, last_heartbeat_at)
+SELECT session_id, MAX(user_id), MIN(ts), MAX(ts), MAX(ts)
+FROM (
+    SELECT session_id, user_id, event_timestamp AS ts FROM devents
+    UNION ALL
+    SELECT session_id, user_id, start_timestamp AS ts FROM recordings
+) AS known_sessions
+GROUP BY session_id;
+
+ALTER TYPE permission_enum ADD VALUE 'sessions:read_any';
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- Separate from the ALTER TYPE since a new enum value can't be used in the transaction that adds it
+INSERT INTO role_permissions (role, permission) VALUES
+    ('admin', 'sessions:read_any'),
+    ('analyst', 'sessions:read_any');
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Recording membership is derived from (session_id, event_timestamp) now that recording_id is gone
+DROP INDEX IF EXISTS devents_session_id_idx;
+CREATE INDEX devents_session_id_event_timestamp_idx ON devents (session_id, event_timestamp);
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Matches the (event_timestamp, id) keyset ordering used to page through a session
+DROP INDEX IF EXISTS devents_session_id_event_timestamp_idx;
+CREATE INDEX devents_session_id_event_timestamp_id_idx ON devents (session_id, event_timestamp, id);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Resume point of the WebSocket ingestion channel, frames up to this sequence number are persisted
+ALTER TABLE sessions ADD COLUMN last_ingested_seq BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Lets clients retry a batch without inserting the same events twice
+ALTER TABLE devents ADD COLUMN client_event_id UUID;
+CREATE UNIQUE INDEX devents_session_id_client_event_id_key ON devents (session_id, client_event_id);
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Dead letters for devents that were sent but could not be stored, kept so bad clients can be debugged
+CREATE TABLE devent_rejections (
+    id UUID PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    session_id UUID, -- NULL when the payload did not carry a readable session id
+    batch_index INTEGER NOT NULL,
+    payload JSONB NOT NULL,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX devent_rejections_user_id_idx ON devent_rejections (user_id, created_at);
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- mouse_action stays the button, the phase says what the button (or the cursor) did
+CREATE TYPE mouse_phase_enum AS ENUM ('down', 'up', 'click', 'double', 'move', 'drag');
+ALTER TABLE devents ADD COLUMN mouse_phase mouse_phase_enum;
+ALTER TABLE devents ADD COLUMN click_count INTEGER;
+
+-- Clients only ever reported single clicks until now
+UPDATE devents SET mouse_phase = 'click', click_count = 1 WHERE mouse_action IS NOT NULL;
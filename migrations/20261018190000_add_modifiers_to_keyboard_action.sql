@@ -0,0 +1,7 @@
+-- Add migration script here
+-- Modifiers held down while the key was pressed, so chords like Cmd+Shift+4 are one event
+CREATE TYPE keyboard_modifier_enum AS ENUM ('shift', 'control', 'alt', 'meta', 'fn', 'caps_lock');
+ALTER TYPE keyboard_action ADD ATTRIBUTE modifiers keyboard_modifier_enum[];
+
+-- Nothing was recorded with modifiers before
+UPDATE devents SET keyboard_action.modifiers = '{}' WHERE keyboard_action IS NOT NULL;
@@ -0,0 +1,32 @@
+-- Add migration script here
+-- Platform keycode and produced text of a key press, so keys outside keyboard_action_key_enum are not lost
+ALTER TYPE keyboard_action ADD ATTRIBUTE keycode INTEGER;
+ALTER TYPE keyboard_action ADD ATTRIBUTE character TEXT;
+
+-- Numpad keys
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_0';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_1';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_2';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_3';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_4';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_5';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_6';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_7';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_8';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_9';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_add';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_subtract';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_multiply';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_divide';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_decimal';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_enter';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'numpad_equals';
+
+-- Media keys
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'media_play_pause';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'media_stop';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'media_next';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'media_previous';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'volume_up';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'volume_down';
+ALTER TYPE keyboard_action_key_enum ADD VALUE 'volume_mute';
@@ -0,0 +1,18 @@
+-- Add migration script here
+-- Displays attached while a session was recorded. Bounds are in the same logical coordinate space as
+-- devents.mouse_x/mouse_y, scale_factor converts them to physical pixels of the recording.
+CREATE TABLE session_displays (
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    display_id TEXT NOT NULL,
+    is_primary BOOLEAN NOT NULL DEFAULT FALSE,
+    x INTEGER NOT NULL,
+    y INTEGER NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    scale_factor DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (session_id, display_id)
+);
+
+ALTER TABLE devents ADD COLUMN display_id TEXT;
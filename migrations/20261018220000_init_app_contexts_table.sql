@@ -0,0 +1,16 @@
+-- Add migration script here
+-- Application and window that received an event. Rows are deduplicated by deriving the id from the
+-- content, so every devent only stores a reference.
+CREATE TABLE app_contexts (
+    id UUID PRIMARY KEY,
+    bundle_id TEXT NOT NULL,
+    app_name TEXT,
+    window_title TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX app_contexts_bundle_id_idx ON app_contexts (bundle_id);
+
+CREATE TYPE window_action_enum AS ENUM ('focus', 'blur');
+ALTER TABLE devents ADD COLUMN window_action window_action_enum;
+ALTER TABLE devents ADD COLUMN app_context_id UUID REFERENCES app_contexts(id);
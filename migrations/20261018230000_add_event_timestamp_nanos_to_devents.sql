@@ -0,0 +1,12 @@
+-- Add migration script here
+-- TIMESTAMPTZ only keeps microseconds, keep the nanoseconds the client sent so fast events don't tie
+ALTER TABLE devents ADD COLUMN event_timestamp_nanos BIGINT;
+
+-- Rows stored so far only have microsecond precision left
+UPDATE devents SET event_timestamp_nanos = (EXTRACT(EPOCH FROM event_timestamp)::NUMERIC * 1000000000)::BIGINT;
+
+ALTER TABLE devents ALTER COLUMN event_timestamp_nanos SET NOT NULL;
+
+-- Devents are ordered and paged by (event_timestamp_nanos, id) now
+DROP INDEX IF EXISTS devents_session_id_event_timestamp_id_idx;
+CREATE INDEX devents_session_id_event_timestamp_nanos_id_idx ON devents (session_id, event_timestamp_nanos, id);
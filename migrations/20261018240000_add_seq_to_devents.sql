@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Per-session sequence number set by the client, used to find events it dropped or reordered
+ALTER TABLE devents ADD COLUMN seq BIGINT;
+CREATE INDEX devents_session_id_seq_idx ON devents (session_id, seq) WHERE seq IS NOT NULL;
@@ -0,0 +1,18 @@
+-- Add migration script here
+-- Offset between the desktop clock and the server clock, sampled once per devent batch or recording
+CREATE TABLE clock_skews (
+    id UUID PRIMARY KEY,
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL,
+    client_timestamp_nanos BIGINT NOT NULL,
+    client_monotonic_nanos BIGINT,
+    received_at_nanos BIGINT NOT NULL,
+    skew_nanos BIGINT NOT NULL, -- received_at_nanos - client_timestamp_nanos
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX clock_skews_session_id_idx ON clock_skews (session_id, received_at_nanos);
+
+-- Timestamps on the server clock, NULL when the client did not send a clock reading
+ALTER TABLE devents ADD COLUMN normalized_timestamp_nanos BIGINT;
+ALTER TABLE recordings ADD COLUMN normalized_start_timestamp_nanos BIGINT;
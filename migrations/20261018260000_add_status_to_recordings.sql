@@ -0,0 +1,15 @@
+-- Add migration script here
+-- Upload lifecycle of a recording: the row is created as pending when the upload URL is handed out,
+-- marked uploaded when the client reports the upload done and verified once the object was found
+CREATE TYPE recording_status_enum AS ENUM ('pending', 'uploaded', 'verified', 'failed');
+
+ALTER TABLE recordings ADD COLUMN status recording_status_enum NOT NULL DEFAULT 'pending';
+ALTER TABLE recordings ADD COLUMN size_bytes BIGINT;
+ALTER TABLE recordings ADD COLUMN etag TEXT;
+ALTER TABLE recordings ADD COLUMN failure_reason TEXT;
+ALTER TABLE recordings ADD COLUMN verified_at TIMESTAMP WITH TIME ZONE;
+
+-- Existing rows may or may not have been uploaded, leave them to be confirmed instead of failing them
+UPDATE recordings SET status = 'uploaded';
+
+CREATE INDEX recordings_pending_created_at_idx ON recordings (created_at) WHERE status = 'pending';
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Id of the multipart upload in progress for a recording, NULL for single PUT uploads and once the
+-- multipart upload was completed or aborted
+ALTER TABLE recordings ADD COLUMN upload_id TEXT;
+
+-- Multipart uploads can take longer than the upload timeout, so pending recordings are failed once they
+-- have been idle that long instead
+DROP INDEX recordings_pending_created_at_idx;
+CREATE INDEX recordings_pending_updated_at_idx ON recordings (updated_at) WHERE status = 'pending';
@@ -1,15 +1,101 @@
 use anyhow::anyhow;
+use chrono::Duration;
 use shuttle_runtime::SecretStore;
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::storage::local::LOCAL_STORAGE_PATH;
+use crate::validation::devents::DeventValidationRules;
+
+/// Paths served without a bearer token, overridable with the comma separated `PUBLIC_PATHS` secret
+pub const DEFAULT_PUBLIC_PATHS: &[&str] = &[
+    "/",
+    "/auth/login",
+    "/auth/signup",
+    "/auth/workos/callback",
+    "/scalar",
+];
+
+/// Largest devent batch accepted by `/devents/create` after decompression, overridable with the
+/// `DEVENT_MAX_BATCH_BYTES` secret
+pub const DEFAULT_MAX_DEVENT_BATCH_BYTES: usize = 16 * 1024 * 1024;
+
+/// Recordings still pending this long after their last upload activity are failed, overridable with the
+/// `RECORDING_UPLOAD_TIMEOUT_SECS` secret
+pub const DEFAULT_RECORDING_UPLOAD_TIMEOUT_SECS: i64 = 2 * 60 * 60;
+
+/// Longest `RECORDING_UPLOAD_TIMEOUT_SECS` accepted
+const MAX_RECORDING_UPLOAD_TIMEOUT_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// How often stale pending recordings are looked for, overridable with the `RECORDING_SWEEP_INTERVAL_SECS`
+/// secret
+pub const DEFAULT_RECORDING_SWEEP_INTERVAL_SECS: u64 = 5 * 60;
+
+/// Longest `RECORDING_SWEEP_INTERVAL_SECS` accepted
+const MAX_RECORDING_SWEEP_INTERVAL_SECS: u64 = 24 * 60 * 60;
+
+/// Bucket recordings are uploaded to, overridable with the `R2_BUCKET` secret
+pub const DEFAULT_R2_BUCKET: &str = "ghost-videos";
+
+/// Directory the local storage backend keeps objects in, overridable with the `LOCAL_STORAGE_DIR` secret
+pub const DEFAULT_LOCAL_STORAGE_DIR: &str = "local-storage";
+
+/// Where clients reach echo, used in the signed URLs of the local storage backend. Overridable with the
+/// `PUBLIC_URL` secret.
+pub const DEFAULT_PUBLIC_URL: &str = "http://localhost:8000";
+
+/// Where recordings are stored, picked with the `STORAGE_BACKEND` secret: `r2` (the default) or `local`
+#[derive(Clone)]
+pub enum StorageConfig {
+    R2 {
+        access_key_id: String,
+        secret_access_key: String,
+        endpoint_url: String,
+        bucket: String,
+    },
+    /// A directory on this machine, clients upload and download through echo itself
+    Local { root: PathBuf, public_url: String },
+}
 
 #[derive(Clone)]
 pub struct AppConfig {
     pub db_connection_uri: String,
     pub jwt_secret: String,
-    pub r2_access_key_id: String,
-    pub r2_secret_access_key: String,
-    pub r2_endpoint_url: String,
+    pub storage: StorageConfig,
     pub workos_api_key: String,
-    pub workos_client_id: String
+    pub workos_client_id: String,
+    pub public_paths: Vec<String>,
+    pub devent_validation: DeventValidationRules,
+    pub max_devent_batch_bytes: usize,
+    pub recording_upload_timeout: Duration,
+    pub recording_sweep_interval: std::time::Duration,
+}
+
+/// Parse an optional secret, falling back to `None` when it is not set
+fn optional_secret<T: FromStr>(secret_store: &SecretStore, key: &str) -> Result<Option<T>, anyhow::Error> {
+    secret_store
+        .get(key)
+        .map(|value| value.parse::<T>().map_err(|_| anyhow!("{} is not valid", key)))
+        .transpose()
+}
+
+/// Parse an optional secret that has to be within `range`, falling back to `default` when it is not set
+fn secret_in_range<T>(
+    secret_store: &SecretStore,
+    key: &str,
+    range: RangeInclusive<T>,
+    default: T,
+) -> Result<T, anyhow::Error>
+where
+    T: FromStr + PartialOrd + Display,
+{
+    let value = optional_secret(secret_store, key)?.unwrap_or(default);
+    if !range.contains(&value) {
+        return Err(anyhow!("{} must be between {} and {}", key, range.start(), range.end()));
+    }
+    Ok(value)
 }
 
 impl AppConfig {
@@ -23,17 +109,32 @@ impl AppConfig {
             .get("DB_CONNECTION_URI")
             .ok_or_else(|| anyhow!("DB_CONNECTION_URI not found"))?;
 
-        let r2_access_key_id = secret_store
-            .get("R2_ACCESS_KEY_ID")
-            .ok_or_else(|| anyhow!("R2_ACCESS_KEY_ID not found"))?;
-
-        let r2_secret_access_key = secret_store
-            .get("R2_SECRET_ACCESS_KEY")
-            .ok_or_else(|| anyhow!("R2_SECRET_ACCESS_KEY not found"))?;
-
-        let r2_endpoint_url = secret_store
-            .get("R2_ENDPOINT_URL")
-            .ok_or_else(|| anyhow!("R2_ENDPOINT_URL not found"))?;
+        let storage = match secret_store.get("STORAGE_BACKEND").as_deref().unwrap_or("r2") {
+            "r2" => StorageConfig::R2 {
+                access_key_id: secret_store
+                    .get("R2_ACCESS_KEY_ID")
+                    .ok_or_else(|| anyhow!("R2_ACCESS_KEY_ID not found"))?,
+                secret_access_key: secret_store
+                    .get("R2_SECRET_ACCESS_KEY")
+                    .ok_or_else(|| anyhow!("R2_SECRET_ACCESS_KEY not found"))?,
+                endpoint_url: secret_store
+                    .get("R2_ENDPOINT_URL")
+                    .ok_or_else(|| anyhow!("R2_ENDPOINT_URL not found"))?,
+                bucket: secret_store
+                    .get("R2_BUCKET")
+                    .unwrap_or_else(|| DEFAULT_R2_BUCKET.to_string()),
+            },
+            "local" => StorageConfig::Local {
+                root: secret_store
+                    .get("LOCAL_STORAGE_DIR")
+                    .unwrap_or_else(|| DEFAULT_LOCAL_STORAGE_DIR.to_string())
+                    .into(),
+                public_url: secret_store
+                    .get("PUBLIC_URL")
+                    .unwrap_or_else(|| DEFAULT_PUBLIC_URL.to_string()),
+            },
+            backend => return Err(anyhow!("STORAGE_BACKEND {} is not valid, use r2 or local", backend)),
+        };
 
         let workos_api_key = secret_store
             .get("WORKOS_API_KEY")
@@ -43,14 +144,118 @@ impl AppConfig {
             .get("WORKOS_CLIENT_ID")
             .ok_or_else(|| anyhow!("WORKOS_CLIENT_ID not found"))?;
 
+        let mut public_paths: Vec<String> = match secret_store.get("PUBLIC_PATHS") {
+            Some(paths) => paths
+                .split(',')
+                .map(|path| path.trim().to_string())
+                .filter(|path| !path.is_empty())
+                .collect(),
+            None => DEFAULT_PUBLIC_PATHS.iter().map(|path| path.to_string()).collect(),
+        };
+        // Signed URLs of the local storage backend carry their own token instead of a bearer token
+        if matches!(storage, StorageConfig::Local { .. }) && !public_paths.iter().any(|path| path == LOCAL_STORAGE_PATH) {
+            public_paths.push(LOCAL_STORAGE_PATH.to_string());
+        }
+
+        let defaults = DeventValidationRules::default();
+        let devent_validation = DeventValidationRules {
+            max_event_age: optional_secret(secret_store, "DEVENT_MAX_EVENT_AGE_SECS")?
+                .map(Duration::seconds)
+                .unwrap_or(defaults.max_event_age),
+            max_future_skew: optional_secret(secret_store, "DEVENT_MAX_FUTURE_SKEW_SECS")?
+                .map(Duration::seconds)
+                .unwrap_or(defaults.max_future_skew),
+            max_coordinate: optional_secret(secret_store, "DEVENT_MAX_COORDINATE")?
+                .unwrap_or(defaults.max_coordinate),
+            max_action_duration_ms: optional_secret(secret_store, "DEVENT_MAX_ACTION_DURATION_MS")?
+                .unwrap_or(defaults.max_action_duration_ms),
+        };
+
+        let max_devent_batch_bytes = optional_secret(secret_store, "DEVENT_MAX_BATCH_BYTES")?
+            .unwrap_or(DEFAULT_MAX_DEVENT_BATCH_BYTES);
+
+        let recording_upload_timeout = Duration::seconds(secret_in_range(
+            secret_store,
+            "RECORDING_UPLOAD_TIMEOUT_SECS",
+            1..=MAX_RECORDING_UPLOAD_TIMEOUT_SECS,
+            DEFAULT_RECORDING_UPLOAD_TIMEOUT_SECS,
+        )?);
+        let recording_sweep_interval = std::time::Duration::from_secs(secret_in_range(
+            secret_store,
+            "RECORDING_SWEEP_INTERVAL_SECS",
+            1..=MAX_RECORDING_SWEEP_INTERVAL_SECS,
+            DEFAULT_RECORDING_SWEEP_INTERVAL_SECS,
+        )?);
+
         Ok(Self {
             db_connection_uri: db_connection_string,
             jwt_secret,
-            r2_access_key_id,
-            r2_secret_access_key,
-            r2_endpoint_url,
+            storage,
             workos_api_key,
-            workos_client_id
+            workos_client_id,
+            public_paths,
+            devent_validation,
+            max_devent_batch_bytes,
+            recording_upload_timeout,
+            recording_sweep_interval,
         })
     }
 }
+
+#[cfg(test)]
+impl AppConfig {
+    /// Config with the defaults and no external services, for tests
+    pub fn for_tests(jwt_secret: &str) -> Self {
+        AppConfig {
+            db_connection_uri: String::new(),
+            jwt_secret: jwt_secret.to_string(),
+            storage: StorageConfig::Local {
+                root: std::env::temp_dir().join(DEFAULT_LOCAL_STORAGE_DIR),
+                public_url: DEFAULT_PUBLIC_URL.to_string(),
+            },
+            workos_api_key: String::new(),
+            workos_client_id: String::new(),
+            public_paths: DEFAULT_PUBLIC_PATHS.iter().map(|path| path.to_string()).collect(),
+            devent_validation: Default::default(),
+            max_devent_batch_bytes: DEFAULT_MAX_DEVENT_BATCH_BYTES,
+            recording_upload_timeout: Duration::seconds(DEFAULT_RECORDING_UPLOAD_TIMEOUT_SECS),
+            recording_sweep_interval: std::time::Duration::from_secs(DEFAULT_RECORDING_SWEEP_INTERVAL_SECS),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret_store(extra: &[(&str, &str)]) -> SecretStore {
+        let mut secrets = serde_json::json!({
+            "JWT_SECRET": "secret",
+            "DB_CONNECTION_URI": "postgres://localhost/echo",
+            "STORAGE_BACKEND": "local",
+            "WORKOS_API_KEY": "key",
+            "WORKOS_CLIENT_ID": "client",
+        });
+        for (key, value) in extra {
+            secrets[*key] = serde_json::Value::from(*value);
+        }
+        serde_json::from_value(secrets).unwrap()
+    }
+
+    #[test]
+    fn recording_sweep_settings_must_be_in_range() {
+        let app_config = AppConfig::new(&secret_store(&[])).unwrap();
+        assert_eq!(app_config.recording_upload_timeout, Duration::seconds(DEFAULT_RECORDING_UPLOAD_TIMEOUT_SECS));
+
+        for (key, value) in [
+            ("RECORDING_SWEEP_INTERVAL_SECS", "0"),
+            ("RECORDING_SWEEP_INTERVAL_SECS", "18446744073709551615"),
+            ("RECORDING_UPLOAD_TIMEOUT_SECS", "0"),
+            ("RECORDING_UPLOAD_TIMEOUT_SECS", "-60"),
+            ("RECORDING_UPLOAD_TIMEOUT_SECS", "9223372036854775807"),
+        ] {
+            let err = AppConfig::new(&secret_store(&[(key, value)])).err().unwrap();
+            assert!(err.to_string().starts_with(key), "{}={} was accepted", key, value);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{error, info};
+
+use crate::config::AppConfig;
+use crate::models::Recording;
+use crate::storage::ObjectStore;
+
+/// Periodically fail recordings whose upload stalled and abort the multipart uploads they left behind, so
+/// storage does not keep their parts. Runs for the lifetime of the process.
+pub fn spawn_recording_sweeper(pool: PgPool, object_store: Arc<dyn ObjectStore>, app_config: Arc<AppConfig>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(app_config.recording_sweep_interval);
+        loop {
+            interval.tick().await;
+            sweep(&pool, object_store.as_ref(), Utc::now() - app_config.recording_upload_timeout).await;
+        }
+    });
+}
+
+/// Fail the recordings with no upload progress since `idle_since` and abort their multipart uploads
+async fn sweep(pool: &PgPool, object_store: &dyn ObjectStore, idle_since: DateTime<Utc>) {
+    touch_multipart_uploads_in_progress(pool, object_store, idle_since).await;
+
+    match Recording::fail_stale_pending(pool, idle_since).await {
+        Ok(0) => {}
+        Ok(failed) => info!("Failed {} recordings that were never uploaded", failed),
+        Err(e) => error!("Error failing stale recordings: {:?}", e),
+    }
+
+    abort_abandoned_uploads(pool, object_store).await;
+}
+
+/// Parts are PUT straight to storage, so a client that presigned its part URLs a while ago can still be
+/// uploading. Ask storage for the parts of stale multipart uploads and note progress on the ones that
+/// received a part since `idle_since`, so they are not failed and aborted.
+async fn touch_multipart_uploads_in_progress(
+    pool: &PgPool,
+    object_store: &dyn ObjectStore,
+    idle_since: DateTime<Utc>,
+) {
+    let uploads = match Recording::get_stale_multipart_uploads(pool, idle_since).await {
+        Ok(uploads) => uploads,
+        Err(e) => {
+            error!("Error getting stale multipart uploads: {:?}", e);
+            return;
+        }
+    };
+
+    for upload in uploads {
+        let Some(upload_id) = upload.upload_id else {
+            continue;
+        };
+        let parts = match object_store.list_parts(upload.r2_object_key, upload_id).await {
+            Ok(parts) => parts,
+            Err(e) => {
+                error!("Error listing parts of recording {}: {:?}", upload.id, e);
+                continue;
+            }
+        };
+        if !parts.iter().any(|part| part.last_modified.is_some_and(|modified| modified >= idle_since)) {
+            continue;
+        }
+        if let Err(e) = Recording::touch(pool, upload.id).await {
+            error!("Error noting upload progress of recording {}: {:?}", upload.id, e);
+        }
+    }
+}
+
+async fn abort_abandoned_uploads(pool: &PgPool, object_store: &dyn ObjectStore) {
+    let uploads = match Recording::get_abandoned_uploads(pool).await {
+        Ok(uploads) => uploads,
+        Err(e) => {
+            error!("Error getting abandoned uploads: {:?}", e);
+            return;
+        }
+    };
+
+    for upload in uploads {
+        let Some(upload_id) = upload.upload_id else {
+            continue;
+        };
+        // Left for the next run if it fails
+        if let Err(e) = object_store.abort_multipart_upload(upload.r2_object_key, upload_id).await {
+            error!("Error aborting multipart upload of recording {}: {:?}", upload.id, e);
+            continue;
+        }
+        if let Err(e) = Recording::clear_upload_id(pool, upload.id).await {
+            error!("Error clearing upload id of recording {}: {:?}", upload.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::web::Bytes;
+    use chrono::Duration;
+    use std::convert::Infallible;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::models::recordings::RecordingStatus;
+    use crate::models::Session;
+    use crate::storage::local::GrantMethod;
+    use crate::storage::LocalStore;
+    use crate::test_support::database;
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn multipart_uploads_still_receiving_parts_are_not_swept() {
+        let pool = database().await;
+        let root = std::env::temp_dir().join(format!("echo-storage-{}", Uuid::new_v4()));
+        let store = LocalStore::new(root.clone(), "http://localhost:8000".to_string(), "secret");
+        let user_id = format!("user_test_{}", Uuid::new_v4());
+        let session = Session {
+            user_id: Some(user_id.clone()),
+            ..Default::default()
+        };
+        Session::insert(&pool, &session).await.unwrap();
+
+        // Both last noted progress three hours ago, only the first received a part since
+        let mut uploads = Vec::new();
+        for start_timestamp_nanos in [1_000, 2_000] {
+            let key = format!("{}/{}.mp4", session.id, start_timestamp_nanos);
+            let recording = Recording::new(
+                &pool,
+                Uuid::new_v4(),
+                session.id,
+                user_id.clone(),
+                key.clone(),
+                start_timestamp_nanos,
+                None,
+                1000,
+            )
+            .await
+            .unwrap();
+            let upload_id = store.create_multipart_upload(key.clone()).await.unwrap();
+            Recording::start_multipart(&pool, recording.id, &upload_id).await.unwrap();
+            sqlx::query("UPDATE recordings SET updated_at = $2 WHERE id = $1")
+                .bind(recording.id)
+                .bind(Utc::now() - Duration::hours(3))
+                .execute(&pool)
+                .await
+                .unwrap();
+            uploads.push((recording.id, key, upload_id));
+        }
+        let (uploading, key, upload_id) = &uploads[0];
+        let url = store.presign_upload_part(key.clone(), upload_id.clone(), 1).await.unwrap();
+        let grant = store.verify(url.split_once("?token=").unwrap().1, key, GrantMethod::Put).unwrap();
+        let part = futures::stream::iter([Ok::<_, Infallible>(Bytes::from_static(b"part"))]);
+        store.write(&grant, part).await.unwrap().unwrap();
+
+        sweep(&pool, &store, Utc::now() - Duration::hours(2)).await;
+
+        let upload = Recording::get_upload(&pool, *uploading).await.unwrap().unwrap();
+        assert_eq!(upload.status, RecordingStatus::Pending);
+        assert_eq!(upload.upload_id.as_ref(), Some(upload_id));
+        assert_eq!(store.list_parts(key.clone(), upload_id.clone()).await.unwrap().len(), 1);
+
+        let (stalled, key, upload_id) = &uploads[1];
+        let upload = Recording::get_upload(&pool, *stalled).await.unwrap().unwrap();
+        assert_eq!(upload.status, RecordingStatus::Failed);
+        assert_eq!(upload.upload_id, None);
+        assert!(store.list_parts(key.clone(), upload_id.clone()).await.is_err());
+
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+}
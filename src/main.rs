@@ -5,21 +5,30 @@ use actix_web::{middleware::Logger, web};
 use shuttle_actix_web::ShuttleActixWeb;
 use shuttle_persist::PersistInstance;
 use shuttle_runtime::SecretStore;
-use config::AppConfig;
+use config::{AppConfig, StorageConfig};
 use sqlx::PgPool;
+use storage::{LocalStore, ObjectStore, S3Store};
 use utoipa::OpenApi;
 use utoipa_scalar::{Scalar, Servable};
 
 mod config;
+mod jobs;
 mod routes;
 mod middleware;
 mod models;
+mod storage;
+#[cfg(test)]
+mod test_support;
 mod types;
+mod validation;
 
 #[derive(Clone)]
 struct AppState {
+    #[allow(dead_code)] // Shuttle persist instance, not read by any route yet
     persist: PersistInstance,
     pool: PgPool,
+    /// Where recordings are uploaded to, picked by `AppConfig::storage`
+    object_store: Arc<dyn ObjectStore>,
     // memory_cache: Cache<String, HashMap<Uuid, Memory>>,
 }
 
@@ -27,6 +36,7 @@ struct AppState {
 #[openapi(
     nest(
         (path = "/", api = routes::hello::ApiDoc),
+        (path = "/auth", api = routes::auth::ApiDoc),
     ),
     tags(
         (name = "echo", description = "Invisibiliy echo API, powering ghost and related services.")
@@ -40,13 +50,34 @@ async fn main(
 ) -> ShuttleActixWeb<impl FnOnce(&mut web::ServiceConfig) + Send + Clone + 'static> {    
     std::env::set_var("RUST_LOG", "actix_web=trace");
     let app_config = Arc::new(AppConfig::new(&secret_store).unwrap());
+
+    // The local store also serves its signed URLs, so it is kept around for the `/storage` routes
+    let (object_store, local_store): (Arc<dyn ObjectStore>, Option<Arc<LocalStore>>) = match &app_config.storage {
+        StorageConfig::R2 { access_key_id, secret_access_key, endpoint_url, bucket } => {
+            let s3_store =
+                S3Store::new(access_key_id.clone(), secret_access_key.clone(), endpoint_url.clone(), bucket.clone()).await;
+            (Arc::new(s3_store), None)
+        }
+        StorageConfig::Local { root, public_url } => {
+            let local_store = Arc::new(LocalStore::new(root.clone(), public_url.clone(), &app_config.jwt_secret));
+            (local_store.clone(), Some(local_store))
+        }
+    };
+
     let app_state = Arc::new(AppState {
         persist,
         pool: PgPool::connect(&app_config.db_connection_uri)
             .await
             .unwrap(),
+        object_store,
     });
 
+    jobs::recordings::spawn_recording_sweeper(
+        app_state.pool.clone(),
+        app_state.object_store.clone(),
+        app_config.clone(),
+    );
+
     let openapi = ApiDoc::openapi();
 
     let config = move |cfg: &mut web::ServiceConfig| {
@@ -56,13 +87,43 @@ async fn main(
                 .service(
                     web::scope("/devents")
                         .service(routes::devents::create_devent)
+                        .service(routes::devent_stream::stream_devents)
+                        .service(routes::devents::export_devents_for_session)
                         .service(routes::devents::get_devents_for_session)
+                        .service(routes::devents::get_app_contexts_for_session)
+                        .service(routes::devents::get_sequence_gaps_for_session)
                         .service(routes::devents::get_devents_for_recording)
                         .service(routes::devents::get_devent)
                 )
+                .service(
+                    web::scope("/sessions")
+                        .service(routes::sessions::start_session)
+                        .service(routes::sessions::heartbeat_session)
+                        .service(routes::sessions::end_session)
+                        .service(routes::sessions::update_session_displays)
+                        .service(routes::sessions::get_session_displays)
+                        .service(routes::sessions::get_session_clock_skews)
+                        .service(routes::sessions::get_my_sessions)
+                        .service(routes::sessions::get_session)
+                )
                 .service(
                     web::scope("/recordings")
                         .service(routes::recordings::fetch_save_url)
+                        .service(routes::recordings::complete_recording)
+                        .service(routes::recordings::create_multipart_upload)
+                        .service(routes::recordings::list_upload_parts)
+                        .service(routes::recordings::get_upload_part_url)
+                        .service(routes::recordings::complete_multipart_upload)
+                        .service(routes::recordings::abort_multipart_upload)
+                        .service(routes::recordings::get_recordings_for_session)
+                        .service(routes::recordings::get_recording_download_url)
+                        .service(routes::recordings::get_recording)
+                )
+                .service(
+                    web::scope("/admin")
+                        .service(routes::admin::get_user_roles)
+                        .service(routes::admin::grant_role)
+                        .service(routes::admin::revoke_role)
                 )
                 .service(
                     web::scope("/auth")
@@ -72,6 +133,16 @@ async fn main(
                         .service(routes::auth::refresh_token)
                         .service(routes::auth::get_user)
                 )
+                .configure(|cfg| {
+                    if let Some(local_store) = &local_store {
+                        cfg.service(
+                            web::scope(storage::local::LOCAL_STORAGE_PATH)
+                                .app_data(web::Data::new(local_store.clone()))
+                                .service(routes::storage::put_object)
+                                .service(routes::storage::get_object),
+                        );
+                    }
+                })
                 .service(Scalar::with_url("/scalar", openapi))
                 .wrap(middleware::auth::AuthenticationMiddleware {
                     app_config: app_config.clone(),
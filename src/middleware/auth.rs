@@ -1,34 +1,26 @@
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    error::ErrorUnauthorized,
+    error::{ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized},
     http::header::AUTHORIZATION,
-    Error, FromRequest, HttpMessage, HttpRequest,
+    web, Error, FromRequest, HttpMessage, HttpRequest,
 };
 use futures_util::future::LocalBoxFuture;
-use jsonwebtoken::{decode, DecodingKey, Validation};
+use chrono::Utc;
+use jsonwebtoken::{decode, errors::ErrorKind, DecodingKey, Validation};
 use std::{
     future::{ready, Ready},
     sync::Arc,
 };
-use tracing::{debug, warn};
+use tracing::{debug, error, warn};
 
-use crate::{types::Claims, AppConfig};
+use crate::models::users::{Permission, Role, User};
+use crate::{types::Claims, AppConfig, AppState};
 
 #[derive(Clone)]
 pub struct AuthenticatedUser {
     pub user_id: String,
 }
 
-impl AuthenticatedUser {
-    pub fn is_admin(&self) -> bool {
-        matches!(
-            self.user_id.as_str(),
-            "user_01HRBJ8FVP3JT28DEWXN6JPKF5" | // Sulaiman skghori
-            "user_01HY5EW9Z5XVE34GZXKH4NC2Y1" |
-            "user_01J12R88378H1Z5R3JCGEPJ6RA"
-        )
-    }
-}
 // This is the trait that actix-web uses to extract the `AuthenticatedUser` from the request
 // This is how we can use `AuthenticatedUser` as a parameter in our route handlers
 // It automatically returns a 401 Unauthorized if the user is not authenticated
@@ -45,6 +37,70 @@ impl FromRequest for AuthenticatedUser {
     }
 }
 
+/// An authenticated user along with the roles and permissions granted to them in the database.
+/// Use this instead of `AuthenticatedUser` in handlers that need to check access.
+#[derive(Clone)]
+pub struct AuthorizedUser {
+    pub user_id: String,
+    pub roles: Vec<Role>,
+    pub permissions: Vec<Permission>,
+}
+
+impl AuthorizedUser {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+
+    /// Returns a 403 Forbidden unless the user has been granted the permission
+    pub fn require(&self, permission: Permission) -> Result<(), Error> {
+        if self.has_permission(permission) {
+            Ok(())
+        } else {
+            warn!("User {} is missing permission {}", self.user_id, permission);
+            Err(ErrorForbidden(format!("Missing permission {}", permission)))
+        }
+    }
+}
+
+// Loads the roles and permissions of the `AuthenticatedUser` from the database. They are cached in the
+// request extensions, so extracting `AuthorizedUser` more than once per request queries only once.
+impl FromRequest for AuthorizedUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<AuthorizedUser, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        if let Some(authorized_user) = req.extensions().get::<AuthorizedUser>() {
+            return Box::pin(ready(Ok(authorized_user.clone())));
+        }
+
+        let req = req.clone();
+        let auth_user = req.extensions().get::<AuthenticatedUser>().cloned();
+        let app_state = req.app_data::<web::Data<Arc<AppState>>>().cloned();
+
+        Box::pin(async move {
+            let auth_user = auth_user.ok_or_else(|| ErrorUnauthorized("User not authenticated"))?;
+            let app_state = app_state
+                .ok_or_else(|| ErrorInternalServerError("Application state not configured"))?;
+
+            let access = User::get_access(&app_state.pool, &auth_user.user_id)
+                .await
+                .map_err(|e| {
+                    error!("Error getting roles and permissions: {:?}", e);
+                    ErrorInternalServerError(e)
+                })?;
+
+            let authorized_user = AuthorizedUser {
+                user_id: auth_user.user_id,
+                roles: access.roles,
+                permissions: access.permissions,
+            };
+            req.extensions_mut().insert(authorized_user.clone());
+
+            Ok(authorized_user)
+        })
+    }
+}
+
 pub struct AuthenticationMiddleware {
     pub app_config: Arc<AppConfig>,
 }
@@ -90,8 +146,27 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-    
-        req.extensions_mut().insert(AuthenticatedUser { user_id: "me".to_string() });
+        if is_public_path(req.path(), &self.app_config.public_paths) {
+            return Box::pin(self.service.call(req));
+        }
+
+        let token = match bearer_token(&req) {
+            Some(token) => token,
+            None => {
+                debug!("Missing bearer token for {}", req.path());
+                return Box::pin(ready(Err(ErrorUnauthorized("Missing bearer token"))));
+            }
+        };
+
+        let claims = match decode_token(&token, &self.app_config.jwt_secret) {
+            Ok(claims) => claims,
+            Err(e) => {
+                warn!("Rejected token for {}: {}", req.path(), e);
+                return Box::pin(ready(Err(ErrorUnauthorized("Invalid token"))));
+            }
+        };
+
+        req.extensions_mut().insert(AuthenticatedUser { user_id: claims.sub });
 
         let fut = self.service.call(req);
 
@@ -101,3 +176,203 @@ where
         })
     }
 }
+
+/// Whether the path is served without authentication. `/` only matches itself, any other entry
+/// also matches its sub paths.
+fn is_public_path(path: &str, public_paths: &[String]) -> bool {
+    public_paths.iter().any(|public_path| {
+        path == public_path
+            || (public_path != "/"
+                && path
+                    .strip_prefix(public_path.as_str())
+                    .is_some_and(|rest| rest.starts_with('/')))
+    })
+}
+
+/// Pull the token out of an `Authorization: Bearer <token>` header
+fn bearer_token(req: &ServiceRequest) -> Option<String> {
+    req.headers()
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+/// Decode and verify a JWT signed by `routes::auth::sign_jwt`, checking the signature, `exp` and `iat`
+fn decode_token(token: &str, jwt_secret: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let mut validation = Validation::default();
+    validation.set_required_spec_claims(&["exp", "iat", "sub"]);
+
+    let claims = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(jwt_secret.as_ref()),
+        &validation,
+    )?
+    .claims;
+
+    // jsonwebtoken only checks that iat is present, so reject tokens issued in the future ourselves
+    let now = Utc::now().timestamp() as u64;
+    if claims.iat as u64 > now + validation.leeway {
+        return Err(ErrorKind::ImmatureSignature.into());
+    }
+
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test as actix_test, web, App};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+
+    fn app_config() -> Arc<AppConfig> {
+        Arc::new(AppConfig::for_tests(SECRET))
+    }
+
+    fn token(secret: &str, iat_offset: i64, exp_offset: i64) -> String {
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            sub: "user_test".to_string(),
+            exp: (now + exp_offset) as usize,
+            iat: (now + iat_offset) as usize,
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_ref()),
+        )
+        .unwrap()
+    }
+
+    async fn call(path: &str, authorization: Option<String>) -> StatusCode {
+        let app = actix_test::init_service(
+            App::new()
+                .wrap(AuthenticationMiddleware {
+                    app_config: app_config(),
+                })
+                .route("/", web::get().to(|| async { "hello" }))
+                .route(
+                    "/auth/user",
+                    web::get().to(|user: AuthenticatedUser| async move { user.user_id }),
+                ),
+        )
+        .await;
+
+        let mut req = actix_test::TestRequest::get().uri(path);
+        if let Some(authorization) = authorization {
+            req = req.insert_header((AUTHORIZATION, authorization));
+        }
+
+        match actix_test::try_call_service(&app, req.to_request()).await {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn valid_token_populates_user() {
+        let app = actix_test::init_service(
+            App::new()
+                .wrap(AuthenticationMiddleware {
+                    app_config: app_config(),
+                })
+                .route(
+                    "/auth/user",
+                    web::get().to(|user: AuthenticatedUser| async move { user.user_id }),
+                ),
+        )
+        .await;
+
+        let req = actix_test::TestRequest::get()
+            .uri("/auth/user")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", token(SECRET, 0, 3600))))
+            .to_request();
+        let body = actix_test::call_and_read_body(&app, req).await;
+
+        assert_eq!(body, "user_test");
+    }
+
+    #[actix_web::test]
+    async fn missing_token_is_rejected() {
+        assert_eq!(call("/auth/user", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            call("/auth/user", Some("Basic dXNlcjpwYXNz".to_string())).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn expired_token_is_rejected() {
+        let expired = token(SECRET, -7200, -3600);
+        assert_eq!(
+            call("/auth/user", Some(format!("Bearer {}", expired))).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn malformed_token_is_rejected() {
+        assert_eq!(
+            call("/auth/user", Some("Bearer not.a.jwt".to_string())).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn wrong_signature_is_rejected() {
+        let forged = token("some-other-secret", 0, 3600);
+        assert_eq!(
+            call("/auth/user", Some(format!("Bearer {}", forged))).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn public_paths_skip_authentication() {
+        assert_eq!(call("/", None).await, StatusCode::OK);
+    }
+
+    #[test]
+    fn token_issued_in_the_future_is_rejected() {
+        assert!(decode_token(&token(SECRET, 3600, 7200), SECRET).is_err());
+        assert!(decode_token(&token(SECRET, 0, 3600), SECRET).is_ok());
+    }
+
+    #[test]
+    fn require_checks_granted_permissions() {
+        let analyst = AuthorizedUser {
+            user_id: "user_test".to_string(),
+            roles: vec![Role::Analyst],
+            permissions: vec![Permission::DeventsReadAny, Permission::RecordingsReadAny],
+        };
+
+        assert!(analyst.has_permission(Permission::DeventsReadAny));
+        assert!(analyst.require(Permission::RecordingsReadAny).is_ok());
+
+        let error = analyst.require(Permission::RolesManage).unwrap_err();
+        assert_eq!(error.as_response_error().status_code(), StatusCode::FORBIDDEN);
+
+        let member = AuthorizedUser {
+            user_id: "user_test".to_string(),
+            roles: vec![Role::Member],
+            permissions: vec![],
+        };
+        assert!(member.require(Permission::DeventsReadAny).is_err());
+    }
+
+    #[test]
+    fn public_path_matching() {
+        let public_paths = app_config().public_paths.clone();
+        assert!(is_public_path("/", &public_paths));
+        assert!(is_public_path("/auth/login", &public_paths));
+        assert!(is_public_path("/scalar/", &public_paths));
+        assert!(!is_public_path("/auth/user", &public_paths));
+        assert!(!is_public_path("/auth/loginx", &public_paths));
+        assert!(!is_public_path("/devents/create", &public_paths));
+    }
+}
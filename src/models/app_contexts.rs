@@ -0,0 +1,106 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, FromRow, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::models::devents::MAX_BIND_PARAMS;
+
+/// Columns written by `AppContext::insert_missing`, one bind parameter each per row
+const INSERT_COLUMNS: &[&str] = &["id", "bundle_id", "app_name", "window_title", "created_at"];
+
+/// Most app contexts that fit in one `INSERT ... VALUES` statement
+const INSERT_CHUNK_SIZE: usize = MAX_BIND_PARAMS / INSERT_COLUMNS.len();
+
+/// Namespace of the content derived `AppContext` ids
+const APP_CONTEXT_NAMESPACE: Uuid = Uuid::from_u128(0x6f1c_2a57_93e4_4b0e_a4d2_1c8e_5b7f_0d39);
+
+/// The application and window that received an event. The id is derived from the content, so the
+/// same app and window always map to the same row and devents only store a reference.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct AppContext {
+    pub id: Uuid,
+    /// Bundle id on macOS, executable name elsewhere
+    pub bundle_id: String,
+    pub app_name: Option<String>,
+    pub window_title: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AppContext {
+    pub fn new(bundle_id: String, app_name: Option<String>, window_title: Option<String>) -> Self {
+        // Serialize as a tuple so no combination of fields can collide with another
+        let key = serde_json::to_vec(&(&bundle_id, &app_name, &window_title)).unwrap_or_default();
+
+        AppContext {
+            id: Uuid::new_v5(&APP_CONTEXT_NAMESPACE, &key),
+            bundle_id,
+            app_name,
+            window_title,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Store the contexts that are not stored yet, duplicates are fine. Call it in the transaction that
+    /// inserts the devents referencing them.
+    pub async fn insert_missing<'a, A>(conn: A, app_contexts: &[AppContext]) -> Result<()>
+    where
+        A: Acquire<'a, Database = Postgres>,
+    {
+        if app_contexts.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = conn.begin().await?;
+        for chunk in app_contexts.chunks(INSERT_CHUNK_SIZE) {
+            let mut query_builder: QueryBuilder<Postgres> =
+                QueryBuilder::new(format!("INSERT INTO app_contexts ({}) ", INSERT_COLUMNS.join(", ")));
+            query_builder.push_values(chunk, |mut b, app_context| {
+                b.push_bind(app_context.id)
+                    .push_bind(app_context.bundle_id.clone())
+                    .push_bind(app_context.app_name.clone())
+                    .push_bind(app_context.window_title.clone())
+                    .push_bind(app_context.created_at);
+            });
+            query_builder.push(" ON CONFLICT (id) DO NOTHING");
+            query_builder.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Every app context devents of the session reference
+    pub async fn get_all_for_session(pool: &PgPool, session_id: Uuid) -> Result<Vec<AppContext>> {
+        let query_str = r#"
+            SELECT a.* FROM app_contexts a
+            WHERE a.id IN (SELECT DISTINCT d.app_context_id FROM devents d WHERE d.session_id = $1)
+            ORDER BY a.bundle_id, a.window_title
+            "#;
+
+        let app_contexts = sqlx::query_as::<_, AppContext>(query_str)
+            .bind(session_id)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(app_contexts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_derived_from_the_content() {
+        let editor = || AppContext::new("com.microsoft.VSCode".to_string(), None, Some("main.rs".to_string()));
+        assert_eq!(editor().id, editor().id);
+
+        let other_window = AppContext::new("com.microsoft.VSCode".to_string(), None, Some("lib.rs".to_string()));
+        assert_ne!(editor().id, other_window.id);
+
+        // The title must not be mistaken for the name
+        let swapped = AppContext::new("com.microsoft.VSCode".to_string(), Some("main.rs".to_string()), None);
+        assert_ne!(editor().id, swapped.id);
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, FromRow, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::models::devents::MAX_BIND_PARAMS;
+
+/// A reading of the desktop clock paired with the server time it was received at. Network latency is
+/// not subtracted, so `skew_nanos` is off by the one-way trip time.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct ClockSkew {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub user_id: String,
+    /// Wall clock of the client when it sent the request
+    pub client_timestamp_nanos: i64,
+    /// Monotonic clock of the client read together with `client_timestamp_nanos`
+    pub client_monotonic_nanos: Option<i64>,
+    pub received_at_nanos: i64,
+    /// Add to a client wall clock timestamp to get server time
+    pub skew_nanos: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Columns written by `ClockSkew::batch_insert`, one bind parameter each per row
+const INSERT_COLUMNS: &[&str] = &[
+    "id",
+    "session_id",
+    "user_id",
+    "client_timestamp_nanos",
+    "client_monotonic_nanos",
+    "received_at_nanos",
+    "skew_nanos",
+    "created_at",
+];
+
+/// Most samples that fit in one `INSERT ... VALUES` statement
+const INSERT_CHUNK_SIZE: usize = MAX_BIND_PARAMS / INSERT_COLUMNS.len();
+
+impl ClockSkew {
+    pub fn new(
+        session_id: Uuid,
+        user_id: String,
+        client_timestamp_nanos: i64,
+        client_monotonic_nanos: Option<i64>,
+        received_at_nanos: i64,
+    ) -> Self {
+        ClockSkew {
+            id: Uuid::new_v4(),
+            session_id,
+            user_id,
+            client_timestamp_nanos,
+            client_monotonic_nanos,
+            received_at_nanos,
+            skew_nanos: received_at_nanos.saturating_sub(client_timestamp_nanos),
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Move a client timestamp onto the server clock. A monotonic reading is preferred because it is
+    /// immune to the wall clock being stepped between the event and this sample.
+    pub fn normalize(&self, timestamp_nanos: i64, monotonic_nanos: Option<i64>) -> i64 {
+        match (self.client_monotonic_nanos, monotonic_nanos) {
+            (Some(sampled_at), Some(monotonic_nanos)) => self
+                .received_at_nanos
+                .saturating_sub(sampled_at.saturating_sub(monotonic_nanos)),
+            _ => timestamp_nanos.saturating_add(self.skew_nanos),
+        }
+    }
+
+    /// Insert samples in chunks that stay under the bind parameter limit, all in one transaction
+    pub async fn batch_insert<'a, A>(conn: A, clock_skews: &[ClockSkew]) -> Result<(), Error>
+    where
+        A: Acquire<'a, Database = Postgres>,
+    {
+        if clock_skews.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = conn.begin().await?;
+        for chunk in clock_skews.chunks(INSERT_CHUNK_SIZE) {
+            let mut query_builder: QueryBuilder<Postgres> =
+                QueryBuilder::new(format!("INSERT INTO clock_skews ({}) ", INSERT_COLUMNS.join(", ")));
+
+            query_builder.push_values(chunk, |mut b, clock_skew| {
+                b.push_bind(clock_skew.id)
+                    .push_bind(clock_skew.session_id)
+                    .push_bind(clock_skew.user_id.clone())
+                    .push_bind(clock_skew.client_timestamp_nanos)
+                    .push_bind(clock_skew.client_monotonic_nanos)
+                    .push_bind(clock_skew.received_at_nanos)
+                    .push_bind(clock_skew.skew_nanos)
+                    .push_bind(clock_skew.created_at);
+            });
+
+            query_builder.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Every sample of a session, oldest first
+    pub async fn get_all_for_session(pool: &PgPool, session_id: Uuid) -> Result<Vec<ClockSkew>> {
+        let query_str = "SELECT * FROM clock_skews WHERE session_id = $1 ORDER BY received_at_nanos, id";
+
+        let clock_skews = sqlx::query_as::<_, ClockSkew>(query_str)
+            .bind(session_id)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(clock_skews)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_prefers_the_monotonic_clock() {
+        // Client wall clock is 5s behind, and was stepped back another second after the event
+        let clock_skew = ClockSkew::new(Uuid::new_v4(), "user".to_string(), 94_000, Some(1_000), 100_000);
+        assert_eq!(clock_skew.skew_nanos, 6_000);
+
+        assert_eq!(clock_skew.normalize(95_000, Some(400)), 99_400);
+        assert_eq!(clock_skew.normalize(95_000, None), 101_000);
+    }
+}
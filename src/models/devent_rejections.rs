@@ -0,0 +1,78 @@
+use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, FromRow, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::models::devents::MAX_BIND_PARAMS;
+
+/// A devent from a batch that was not stored, with the payload exactly as the client sent it
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct DeventRejection {
+    pub id: Uuid,
+    pub user_id: String,
+    pub session_id: Option<Uuid>,
+    /// Position of the event in the batch it was sent in
+    pub batch_index: i32,
+    pub payload: serde_json::Value,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Columns written by `DeventRejection::batch_insert`, one bind parameter each per row
+const INSERT_COLUMNS: &[&str] = &["id", "user_id", "session_id", "batch_index", "payload", "reason", "created_at"];
+
+/// Most rejections that fit in one `INSERT ... VALUES` statement
+const INSERT_CHUNK_SIZE: usize = MAX_BIND_PARAMS / INSERT_COLUMNS.len();
+
+impl DeventRejection {
+    pub fn new(user_id: String, batch_index: usize, payload: serde_json::Value, reason: String) -> Self {
+        // Keep whatever session the payload names, even if the rest of it is unreadable
+        let session_id = payload
+            .get("session_id")
+            .and_then(|session_id| session_id.as_str())
+            .and_then(|session_id| Uuid::parse_str(session_id).ok());
+
+        DeventRejection {
+            id: Uuid::new_v4(),
+            user_id,
+            session_id,
+            batch_index: batch_index as i32,
+            payload,
+            reason,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Insert rejections in chunks that stay under the bind parameter limit, all in one transaction. Inside
+    /// a caller's transaction they are stored atomically with the accepted devents of the same batch.
+    pub async fn batch_insert<'a, A>(conn: A, rejections: &[DeventRejection]) -> Result<(), Error>
+    where
+        A: Acquire<'a, Database = Postgres>,
+    {
+        if rejections.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = conn.begin().await?;
+        for chunk in rejections.chunks(INSERT_CHUNK_SIZE) {
+            let mut query_builder: QueryBuilder<Postgres> =
+                QueryBuilder::new(format!("INSERT INTO devent_rejections ({}) ", INSERT_COLUMNS.join(", ")));
+
+            query_builder.push_values(chunk, |mut b, rejection| {
+                b.push_bind(rejection.id)
+                    .push_bind(rejection.user_id.clone())
+                    .push_bind(rejection.session_id)
+                    .push_bind(rejection.batch_index)
+                    .push_bind(rejection.payload.clone())
+                    .push_bind(rejection.reason.clone())
+                    .push_bind(rejection.created_at);
+            });
+
+            query_builder.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
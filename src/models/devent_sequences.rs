@@ -0,0 +1,168 @@
+use anyhow::Error;
+use futures::TryStreamExt;
+use serde::Serialize;
+use sqlx::{Acquire, PgPool, Postgres};
+use uuid::Uuid;
+
+/// Most entries listed per kind of issue, the totals still count every one
+pub const MAX_REPORTED_ISSUES: usize = 1000;
+
+/// Sequence numbers that never arrived, `from` and `to` are inclusive
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SequenceRange {
+    pub from: i64,
+    pub to: i64,
+}
+
+/// A sequence number that arrived more than once
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DuplicateSequence {
+    pub seq: i64,
+    pub count: usize,
+}
+
+/// An event that came after an event with a higher sequence number
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Reordering {
+    pub seq: i64,
+    pub after_seq: i64,
+}
+
+/// Problems with the sequence numbers a client sent for a session
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SequenceReport {
+    /// Highest sequence number looked at
+    pub last_seq: Option<i64>,
+    pub missing: Vec<SequenceRange>,
+    /// How many sequence numbers are missing in total
+    pub missing_count: i64,
+    pub duplicates: Vec<DuplicateSequence>,
+    pub duplicate_count: usize,
+    pub reorderings: Vec<Reordering>,
+    pub reordering_count: usize,
+}
+
+impl SequenceReport {
+    /// Check sequence numbers in the order the events happened. Numbers at or below `after` were
+    /// already checked and are skipped, the first number expected is `after + 1`.
+    pub fn analyze(after: i64, seqs: impl IntoIterator<Item = i64>) -> Self {
+        let mut report = SequenceReport::default();
+        let mut sorted = Vec::new();
+        let mut highest = after;
+
+        for seq in seqs.into_iter().filter(|seq| *seq > after) {
+            if seq < highest {
+                report.reordering_count += 1;
+                if report.reorderings.len() < MAX_REPORTED_ISSUES {
+                    report.reorderings.push(Reordering { seq, after_seq: highest });
+                }
+            }
+            highest = highest.max(seq);
+            sorted.push(seq);
+        }
+        sorted.sort_unstable();
+
+        let mut expected = after + 1;
+        for run in sorted.chunk_by(|a, b| a == b) {
+            let seq = run[0];
+            if seq > expected {
+                report.missing_count += seq - expected;
+                if report.missing.len() < MAX_REPORTED_ISSUES {
+                    report.missing.push(SequenceRange { from: expected, to: seq - 1 });
+                }
+            }
+            if run.len() > 1 {
+                report.duplicate_count += 1;
+                if report.duplicates.len() < MAX_REPORTED_ISSUES {
+                    report.duplicates.push(DuplicateSequence { seq, count: run.len() });
+                }
+            }
+            expected = seq + 1;
+        }
+
+        report.last_seq = sorted.last().copied();
+        report
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.missing_count == 0 && self.duplicate_count == 0 && self.reordering_count == 0
+    }
+
+    /// Check every sequence number stored for a session, in `(event_timestamp_nanos, id)` order
+    pub async fn for_session(pool: &PgPool, session_id: Uuid) -> Result<Self, Error> {
+        let query_str = r#"
+            SELECT seq FROM devents
+            WHERE session_id = $1 AND seq IS NOT NULL
+            ORDER BY event_timestamp_nanos, id
+        "#;
+
+        let seqs: Vec<i64> = sqlx::query_scalar::<_, i64>(query_str)
+            .bind(session_id)
+            .fetch(pool)
+            .try_collect()
+            .await?;
+
+        Ok(SequenceReport::analyze(0, seqs))
+    }
+
+    /// Highest sequence number stored for a session, 0 if it has none. Locks the session row until the
+    /// surrounding transaction ends, so concurrent batches for one session are checked one after another
+    /// and each sees the numbers the one before it stored.
+    pub async fn last_seq_for_session<'a, A>(conn: A, session_id: Uuid) -> Result<i64, Error>
+    where
+        A: Acquire<'a, Database = Postgres>,
+    {
+        let mut conn = conn.acquire().await?;
+
+        sqlx::query("SELECT id FROM sessions WHERE id = $1 FOR UPDATE")
+            .bind(session_id)
+            .execute(&mut *conn)
+            .await?;
+
+        let query_str = "SELECT MAX(seq) FROM devents WHERE session_id = $1";
+
+        let last_seq = sqlx::query_scalar::<_, Option<i64>>(query_str)
+            .bind(session_id)
+            .fetch_one(&mut *conn)
+            .await?;
+
+        Ok(last_seq.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_sequence() {
+        let report = SequenceReport::analyze(0, [1, 2, 3, 4]);
+        assert!(report.is_clean());
+        assert_eq!(report.last_seq, Some(4));
+    }
+
+    #[test]
+    fn missing_duplicates_and_reorderings() {
+        let report = SequenceReport::analyze(0, [1, 2, 5, 4, 4, 9]);
+        assert_eq!(
+            report.missing,
+            vec![SequenceRange { from: 3, to: 3 }, SequenceRange { from: 6, to: 8 }]
+        );
+        assert_eq!(report.missing_count, 4);
+        assert_eq!(report.duplicates, vec![DuplicateSequence { seq: 4, count: 2 }]);
+        assert_eq!(
+            report.reorderings,
+            vec![Reordering { seq: 4, after_seq: 5 }, Reordering { seq: 4, after_seq: 5 }]
+        );
+        assert_eq!(report.last_seq, Some(9));
+    }
+
+    #[test]
+    fn numbers_already_checked_are_skipped() {
+        let report = SequenceReport::analyze(10, [3, 11, 12]);
+        assert!(report.is_clean());
+
+        let report = SequenceReport::analyze(10, [13]);
+        assert_eq!(report.missing, vec![SequenceRange { from: 11, to: 12 }]);
+    }
+}
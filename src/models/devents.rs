@@ -1,9 +1,17 @@
 use chrono::{DateTime, Utc, TimeZone};
+use futures::stream::BoxStream;
 use serde::{Deserialize, Serialize};
-use sqlx::{query, FromRow, PgPool, Type, Postgres, QueryBuilder};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::types::{PgRecordDecoder, PgRecordEncoder};
+use sqlx::postgres::{PgArgumentBuffer, PgHasArrayType, PgTypeInfo, PgValueRef};
+use sqlx::{query, Acquire, Decode, Encode, Executor, FromRow, PgPool, Type, Postgres, QueryBuilder};
 use uuid::Uuid;
 use std::fmt;
-use anyhow::{Result, Error};
+use anyhow::{anyhow, Result, Error};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+use crate::models::shortcuts::Shortcut;
 
 #[derive(Clone, Debug, Serialize, Deserialize, Type)]
 #[sqlx(type_name = "mouse_action_enum", rename_all = "lowercase")] // SQL value name
@@ -26,7 +34,49 @@ impl fmt::Display for MouseAction {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+/// What happened in a mouse event. `MouseAction` is the button involved, a `Move` has none.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "mouse_phase_enum", rename_all = "lowercase")] // SQL value name
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum MousePhase {
+    Down,
+    Up,
+    Click,
+    Double,
+    Move,
+    Drag,
+}
+
+impl MousePhase {
+    /// Whether the phase is about a button, every phase but a plain cursor move is
+    pub fn needs_button(self) -> bool {
+        self != MousePhase::Move
+    }
+
+    /// Click count assumed when the client does not send one
+    pub fn default_click_count(self) -> Option<i32> {
+        match self {
+            MousePhase::Down | MousePhase::Up | MousePhase::Click => Some(1),
+            MousePhase::Double => Some(2),
+            MousePhase::Move | MousePhase::Drag => None,
+        }
+    }
+}
+
+impl fmt::Display for MousePhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MousePhase::Down => write!(f, "down"),
+            MousePhase::Up => write!(f, "up"),
+            MousePhase::Click => write!(f, "click"),
+            MousePhase::Double => write!(f, "double"),
+            MousePhase::Move => write!(f, "move"),
+            MousePhase::Drag => write!(f, "drag"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
 #[sqlx(type_name = "keyboard_action_key_enum", rename_all = "lowercase")] // SQL value name
 #[serde(rename_all = "lowercase")] // JSON value name
 pub enum KeyboardActionKey {
@@ -167,16 +217,189 @@ pub enum KeyboardActionKey {
     Period,
     Slash,
     Backslash,
+    // Numpad Keys
+    #[sqlx(rename = "numpad_0")]
+    #[serde(rename = "numpad_0")]
+    Numpad0,
+    #[sqlx(rename = "numpad_1")]
+    #[serde(rename = "numpad_1")]
+    Numpad1,
+    #[sqlx(rename = "numpad_2")]
+    #[serde(rename = "numpad_2")]
+    Numpad2,
+    #[sqlx(rename = "numpad_3")]
+    #[serde(rename = "numpad_3")]
+    Numpad3,
+    #[sqlx(rename = "numpad_4")]
+    #[serde(rename = "numpad_4")]
+    Numpad4,
+    #[sqlx(rename = "numpad_5")]
+    #[serde(rename = "numpad_5")]
+    Numpad5,
+    #[sqlx(rename = "numpad_6")]
+    #[serde(rename = "numpad_6")]
+    Numpad6,
+    #[sqlx(rename = "numpad_7")]
+    #[serde(rename = "numpad_7")]
+    Numpad7,
+    #[sqlx(rename = "numpad_8")]
+    #[serde(rename = "numpad_8")]
+    Numpad8,
+    #[sqlx(rename = "numpad_9")]
+    #[serde(rename = "numpad_9")]
+    Numpad9,
+    #[sqlx(rename = "numpad_add")]
+    #[serde(rename = "numpad_add")]
+    NumpadAdd,
+    #[sqlx(rename = "numpad_subtract")]
+    #[serde(rename = "numpad_subtract")]
+    NumpadSubtract,
+    #[sqlx(rename = "numpad_multiply")]
+    #[serde(rename = "numpad_multiply")]
+    NumpadMultiply,
+    #[sqlx(rename = "numpad_divide")]
+    #[serde(rename = "numpad_divide")]
+    NumpadDivide,
+    #[sqlx(rename = "numpad_decimal")]
+    #[serde(rename = "numpad_decimal")]
+    NumpadDecimal,
+    #[sqlx(rename = "numpad_enter")]
+    #[serde(rename = "numpad_enter")]
+    NumpadEnter,
+    #[sqlx(rename = "numpad_equals")]
+    #[serde(rename = "numpad_equals")]
+    NumpadEquals,
+    // Media Keys
+    #[sqlx(rename = "media_play_pause")]
+    #[serde(rename = "media_play_pause")]
+    MediaPlayPause,
+    #[sqlx(rename = "media_stop")]
+    #[serde(rename = "media_stop")]
+    MediaStop,
+    #[sqlx(rename = "media_next")]
+    #[serde(rename = "media_next")]
+    MediaNext,
+    #[sqlx(rename = "media_previous")]
+    #[serde(rename = "media_previous")]
+    MediaPrevious,
+    #[sqlx(rename = "volume_up")]
+    #[serde(rename = "volume_up")]
+    VolumeUp,
+    #[sqlx(rename = "volume_down")]
+    #[serde(rename = "volume_down")]
+    VolumeDown,
+    #[sqlx(rename = "volume_mute")]
+    #[serde(rename = "volume_mute")]
+    VolumeMute,
     #[serde(other)]
     Unknown
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Type)]
-#[sqlx(type_name = "keyboard_action")] // SQL value name
+/// A window of `Devent::app_context_id` gaining or losing focus
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "window_action_enum", rename_all = "lowercase")] // SQL value name
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum WindowAction {
+    Focus,
+    Blur,
+}
+
+/// Modifier held down while a key was pressed. The Mac keys are sent as their PC counterparts,
+/// `option` as `alt` and `command` as `meta`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "keyboard_modifier_enum", rename_all = "lowercase")] // SQL value name
 #[serde(rename_all = "lowercase")] // JSON value name
+pub enum KeyboardModifier {
+    Shift,
+    Control,
+    #[serde(alias = "option")]
+    Alt,
+    #[serde(alias = "command")]
+    Meta,
+    Fn,
+    #[sqlx(rename = "caps_lock")]
+    #[serde(rename = "caps_lock")]
+    CapsLock,
+}
+
+impl PgHasArrayType for KeyboardModifier {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_keyboard_modifier_enum")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", into = "LabeledKeyboardAction")] // JSON value name
 pub struct KeyboardAction {
     pub key: KeyboardActionKey,
     pub duration: i32,
+    #[serde(default)]
+    pub modifiers: Vec<KeyboardModifier>,
+    /// Platform keycode as reported by the OS, set for every key including `Unknown` ones
+    #[serde(default)]
+    pub keycode: Option<i32>,
+    /// Text the key press produced with the current layout or IME, if any
+    #[serde(default)]
+    pub character: Option<String>,
+}
+
+// The `keyboard_action` composite type, written out because the sqlx 0.7 `Type` derive does not compile
+// for composites with `Option` fields
+impl Type<Postgres> for KeyboardAction {
+    fn type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("keyboard_action")
+    }
+}
+
+impl<'q> Encode<'q, Postgres> for KeyboardAction {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        let mut encoder = PgRecordEncoder::new(buf);
+        encoder
+            .encode(&self.key)
+            .encode(self.duration)
+            .encode(&self.modifiers)
+            .encode(self.keycode)
+            .encode(&self.character);
+        encoder.finish();
+        IsNull::No
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for KeyboardAction {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        let mut decoder = PgRecordDecoder::new(value)?;
+        Ok(KeyboardAction {
+            key: decoder.try_decode()?,
+            duration: decoder.try_decode()?,
+            modifiers: decoder.try_decode()?,
+            keycode: decoder.try_decode()?,
+            character: decoder.try_decode()?,
+        })
+    }
+}
+
+/// JSON form of a `KeyboardAction` in responses, labelled with the shortcut it forms
+#[derive(Serialize)]
+pub struct LabeledKeyboardAction {
+    pub key: KeyboardActionKey,
+    pub duration: i32,
+    pub modifiers: Vec<KeyboardModifier>,
+    pub keycode: Option<i32>,
+    pub character: Option<String>,
+    pub shortcut: Option<Shortcut>,
+}
+
+impl From<KeyboardAction> for LabeledKeyboardAction {
+    fn from(keyboard_action: KeyboardAction) -> Self {
+        LabeledKeyboardAction {
+            shortcut: keyboard_action.shortcut(),
+            key: keyboard_action.key,
+            duration: keyboard_action.duration,
+            modifiers: keyboard_action.modifiers,
+            keycode: keyboard_action.keycode,
+            character: keyboard_action.character,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Type)]
@@ -193,30 +416,204 @@ pub struct ScrollAction {
 pub struct Devent {
     pub id: Uuid,
     pub session_id: Uuid,
-    pub recording_id: Uuid,
+    pub user_id: Option<String>,
+    /// Id the client gave the event, unique within a session so retried batches are not inserted twice
+    pub client_event_id: Option<Uuid>,
+    /// Per-session sequence number set by the client
+    pub seq: Option<i64>,
     pub mouse_action: Option<MouseAction>,
+    pub mouse_phase: Option<MousePhase>,
+    /// Clicks in a row as counted by the OS, 2 for the second click of a double click
+    pub click_count: Option<i32>,
     pub keyboard_action: Option<KeyboardAction>,
     pub scroll_action: Option<ScrollAction>,
+    pub window_action: Option<WindowAction>,
+    /// Application and window that received the event, see `AppContext`
+    pub app_context_id: Option<Uuid>,
     pub mouse_x: i32,
     pub mouse_y: i32,
+    /// Display the event happened on, see `SessionDisplay`. `None` means the primary display.
+    pub display_id: Option<String>,
+    /// Rounded to microseconds by Postgres, see `event_timestamp_nanos` for the exact value
     pub event_timestamp: DateTime<Utc>,
+    /// Nanoseconds since the epoch as sent by the client, devents are ordered by it
+    pub event_timestamp_nanos: i64,
+    /// `event_timestamp_nanos` moved onto the server clock, unset if the client did not send its clock
+    pub normalized_timestamp_nanos: Option<i64>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub deleted_at: Option<DateTime<Utc>>,
 }
 
+/// Columns written by `Devent::batch_insert`, one bind parameter each per row
+const INSERT_COLUMNS: &[&str] = &[
+    "id",
+    "session_id",
+    "user_id",
+    "client_event_id",
+    "seq",
+    "mouse_action",
+    "mouse_phase",
+    "click_count",
+    "keyboard_action",
+    "scroll_action",
+    "window_action",
+    "app_context_id",
+    "mouse_x",
+    "mouse_y",
+    "display_id",
+    "event_timestamp",
+    "event_timestamp_nanos",
+    "normalized_timestamp_nanos",
+    "deleted_at",
+    "created_at",
+    "updated_at",
+];
+
+/// Postgres allows at most this many bind parameters in one statement
+pub(crate) const MAX_BIND_PARAMS: usize = 65535;
+
+/// Most devents that fit in one `INSERT ... VALUES` statement
+const INSERT_CHUNK_SIZE: usize = MAX_BIND_PARAMS / INSERT_COLUMNS.len();
+
+/// Position of the last devent of a page. Devents are paged in `(event_timestamp_nanos, id)` order and
+/// the cursor is handed to clients as an opaque string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeventCursor {
+    pub event_timestamp_nanos: i64,
+    pub id: Uuid,
+}
+
+impl DeventCursor {
+    pub fn encode(&self) -> String {
+        let raw = format!("n{}:{}", self.event_timestamp_nanos, self.id);
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, Error> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor)?)?;
+        let (timestamp, id) = raw
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Malformed cursor"))?;
+        // Cursors handed out before devents were ordered by nanoseconds hold microseconds
+        let event_timestamp_nanos = match timestamp.strip_prefix('n') {
+            Some(nanos) => nanos.parse()?,
+            None => timestamp
+                .parse::<i64>()?
+                .checked_mul(1000)
+                .ok_or_else(|| anyhow!("Cursor timestamp out of range"))?,
+        };
+
+        Ok(DeventCursor {
+            event_timestamp_nanos,
+            id: Uuid::parse_str(id)?,
+        })
+    }
+}
+
+/// Nanoseconds since the epoch, saturating outside of the years 1677 to 2262
+pub(crate) fn timestamp_nanos(timestamp: DateTime<Utc>) -> i64 {
+    timestamp.timestamp_nanos_opt().unwrap_or(if timestamp.timestamp() < 0 {
+        i64::MIN
+    } else {
+        i64::MAX
+    })
+}
+
+/// Time range, position and size of a page of devents
+#[derive(Debug, Clone)]
+pub struct DeventPageFilter {
+    /// Inclusive lower bound on `event_timestamp`
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive upper bound on `event_timestamp`
+    pub to: Option<DateTime<Utc>>,
+    pub after: Option<DeventCursor>,
+    /// Only devents received by this application
+    pub bundle_id: Option<String>,
+    pub limit: i64,
+}
+
+impl DeventPageFilter {
+    /// Append the range and keyset conditions, ordering and limit, with devents aliased as `d`
+    fn push_conditions(&self, query_builder: &mut QueryBuilder<'_, Postgres>) {
+        if let Some(from) = self.from {
+            query_builder
+                .push(" AND d.event_timestamp_nanos >= ")
+                .push_bind(timestamp_nanos(from));
+        }
+        if let Some(to) = self.to {
+            query_builder
+                .push(" AND d.event_timestamp_nanos < ")
+                .push_bind(timestamp_nanos(to));
+        }
+        if let Some(bundle_id) = &self.bundle_id {
+            query_builder
+                .push(" AND d.app_context_id IN (SELECT a.id FROM app_contexts a WHERE a.bundle_id = ")
+                .push_bind(bundle_id.clone())
+                .push(")");
+        }
+        if let Some(after) = &self.after {
+            query_builder
+                .push(" AND (d.event_timestamp_nanos, d.id) > (")
+                .push_bind(after.event_timestamp_nanos)
+                .push(", ")
+                .push_bind(after.id)
+                .push(")");
+        }
+        // Fetch one extra row to know whether there is another page
+        query_builder
+            .push(" ORDER BY d.event_timestamp_nanos, d.id LIMIT ")
+            .push_bind(self.limit + 1);
+    }
+
+    /// Drop the extra row fetched by `push_conditions` and return the cursor of the next page, if any
+    fn next_cursor<T>(&self, rows: &mut Vec<T>, devent: impl Fn(&T) -> &Devent) -> Option<DeventCursor> {
+        if rows.len() as i64 <= self.limit {
+            return None;
+        }
+
+        rows.truncate(self.limit as usize);
+        rows.last().map(|row| {
+            let devent = devent(row);
+            DeventCursor {
+                event_timestamp_nanos: devent.event_timestamp_nanos,
+                id: devent.id,
+            }
+        })
+    }
+}
+
+/// A devent along with where it lands in the video of the recording it was captured during
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct RecordingDevent {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub devent: Devent,
+    /// Milliseconds from the start of the recording
+    pub offset_ms: i64,
+}
+
 impl Default for Devent {
     fn default() -> Self {
         Devent {
             id: Uuid::new_v4(),
             session_id: Uuid::new_v4(),
-            recording_id: Uuid::new_v4(),
+            user_id: None,
+            client_event_id: None,
+            seq: None,
             mouse_action: None,
+            mouse_phase: None,
+            click_count: None,
             keyboard_action: None,
             scroll_action: None,
+            window_action: None,
+            app_context_id: None,
             mouse_x: 0,
             mouse_y: 0,
+            display_id: None,
             event_timestamp: Utc::now(),
+            event_timestamp_nanos: timestamp_nanos(Utc::now()),
+            normalized_timestamp_nanos: None,
             deleted_at: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
@@ -225,10 +622,11 @@ impl Default for Devent {
 }
 
 impl Devent {
-    #[allow(clippy::too_many_arguments)]
+    #[allow(clippy::too_many_arguments, dead_code)]
     pub async fn new(
         pool: &PgPool,
         session_id: Uuid,
+        user_id: String,
         mouse_action: Option<MouseAction>,
         keyboard_action: Option<KeyboardAction>,
         scroll_action: Option<ScrollAction>,
@@ -241,28 +639,32 @@ impl Devent {
         let devent = Devent {
             id: Uuid::new_v4(),
             session_id,
+            user_id: Some(user_id),
             mouse_action,
             keyboard_action,
             scroll_action,
             mouse_x,
             mouse_y,
             event_timestamp,
+            event_timestamp_nanos,
             ..Default::default()
         };
 
         query!(
             r#"
-            INSERT INTO devents (id, session_id, mouse_action, keyboard_action, scroll_action, mouse_x, mouse_y, event_timestamp, deleted_at, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            INSERT INTO devents (id, session_id, user_id, mouse_action, keyboard_action, scroll_action, mouse_x, mouse_y, event_timestamp, event_timestamp_nanos, deleted_at, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
             "#,
             devent.id,
             devent.session_id,
+            devent.user_id,
             devent.mouse_action.clone() as Option<MouseAction>,
             devent.keyboard_action.clone() as Option<KeyboardAction>,
             devent.scroll_action.clone() as Option<ScrollAction>,
             devent.mouse_x,
             devent.mouse_y,
             devent.event_timestamp,
+            devent.event_timestamp_nanos,
             devent.deleted_at,
             devent.created_at,
             devent.updated_at
@@ -284,26 +686,67 @@ impl Devent {
         Ok(devent)
     }
 
-    pub async fn get_all_for_session(pool: &PgPool, session_id: Uuid) -> Result<Vec<Devent>, Error> {
-        let query_str = "SELECT * FROM devents WHERE session_id = $1";
+    /// Stream every devent of a session in `(event_timestamp_nanos, id)` order without buffering them in memory
+    pub fn stream_for_session(pool: &PgPool, session_id: Uuid) -> BoxStream<'_, Result<Devent, sqlx::Error>> {
+        let query_str = "SELECT * FROM devents WHERE session_id = $1 ORDER BY event_timestamp_nanos, id";
 
-        let devents = sqlx::query_as::<_, Devent>(query_str)
+        sqlx::query_as::<_, Devent>(query_str)
             .bind(session_id)
+            .fetch(pool)
+    }
+
+    /// A page of a session's devents in `(event_timestamp_nanos, id)` order, plus the cursor of the next page
+    pub async fn get_page_for_session(
+        pool: &PgPool,
+        session_id: Uuid,
+        filter: &DeventPageFilter,
+    ) -> Result<(Vec<Devent>, Option<DeventCursor>), Error> {
+        let mut query_builder: QueryBuilder<Postgres> =
+            QueryBuilder::new("SELECT d.* FROM devents d WHERE d.session_id = ");
+        query_builder.push_bind(session_id);
+        filter.push_conditions(&mut query_builder);
+
+        let mut devents = query_builder
+            .build_query_as::<Devent>()
             .fetch_all(pool)
             .await?;
 
-        Ok(devents)
+        let next_cursor = filter.next_cursor(&mut devents, |devent| devent);
+        Ok((devents, next_cursor))
     }
 
-    pub async fn get_all_for_recording(pool: &PgPool, recording_id: Uuid) -> Result<Vec<Devent>, Error> {
-        let query_str = "SELECT * FROM devents WHERE recording_id = $1";
+    /// A page of the devents captured while a recording was running. An event belongs to a recording when
+    /// it is in the same session and its timestamp falls within `[start_timestamp, start_timestamp + duration]`.
+    /// Deleted recordings have no devents.
+    pub async fn get_page_for_recording(
+        pool: &PgPool,
+        recording_id: Uuid,
+        filter: &DeventPageFilter,
+    ) -> Result<(Vec<RecordingDevent>, Option<DeventCursor>), Error> {
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            r#"
+            SELECT d.*, (d.event_timestamp_nanos - r.start_nanos) / 1000000 AS offset_ms
+            FROM devents d
+            JOIN (
+                SELECT session_id, (EXTRACT(EPOCH FROM start_timestamp)::NUMERIC * 1000000000)::BIGINT AS start_nanos, duration::BIGINT * 1000000 AS duration_nanos
+                FROM recordings
+                WHERE deleted_at IS NULL AND id = "#,
+        );
+        query_builder.push_bind(recording_id);
+        query_builder.push(
+            r#"
+            ) r ON r.session_id = d.session_id
+            WHERE d.event_timestamp_nanos BETWEEN r.start_nanos AND r.start_nanos + r.duration_nanos"#,
+        );
+        filter.push_conditions(&mut query_builder);
 
-        let devents = sqlx::query_as::<_, Devent>(query_str)
-            .bind(recording_id)
+        let mut devents = query_builder
+            .build_query_as::<RecordingDevent>()
             .fetch_all(pool)
             .await?;
 
-        Ok(devents)
+        let next_cursor = filter.next_cursor(&mut devents, |recording_devent| &recording_devent.devent);
+        Ok((devents, next_cursor))
     }
 }
 
@@ -311,6 +754,7 @@ impl Devent {
     #[allow(clippy::too_many_arguments)]
     pub fn prepare_for_insert(
         session_id: Uuid,
+        user_id: String,
         mouse_action: Option<MouseAction>,
         keyboard_action: Option<KeyboardAction>,
         scroll_action: Option<ScrollAction>,
@@ -323,37 +767,224 @@ impl Devent {
         Devent {
             id: Uuid::new_v4(),
             session_id,
+            user_id: Some(user_id),
             mouse_action,
             keyboard_action,
             scroll_action,
             mouse_x,
             mouse_y,
             event_timestamp,
+            event_timestamp_nanos,
             ..Default::default()
         }
     }
 
-    pub async fn batch_insert(pool: &PgPool, devents: &[Devent]) -> Result<(), Error> {
-        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
-            "INSERT INTO devents (id, session_id, mouse_action, keyboard_action, scroll_action, mouse_x, mouse_y, event_timestamp, deleted_at, created_at, updated_at) "
-        );
+    /// Insert any number of devents atomically. Postgres caps a statement at 65535 bind parameters, so
+    /// larger batches are split into chunks of `INSERT_CHUNK_SIZE` that are inserted in one transaction.
+    /// A batch that fits in one chunk is a single statement and skips the extra `BEGIN`/`COMMIT` round
+    /// trips. Takes the pool or a connection, inside a caller's transaction the chunks go into a savepoint.
+    ///
+    /// Devents whose `client_event_id` is already stored for the session are skipped, returns how many
+    /// devents were actually inserted.
+    pub async fn batch_insert<'a, A>(conn: A, devents: &[Devent]) -> Result<u64, Error>
+    where
+        A: Acquire<'a, Database = Postgres>,
+    {
+        if devents.is_empty() {
+            return Ok(0);
+        }
+        if devents.len() <= INSERT_CHUNK_SIZE {
+            let mut conn = conn.acquire().await?;
+            return Devent::insert_chunk(&mut *conn, devents).await;
+        }
+
+        let mut inserted = 0;
+        let mut tx = conn.begin().await?;
+        for chunk in devents.chunks(INSERT_CHUNK_SIZE) {
+            inserted += Devent::insert_chunk(&mut *tx, chunk).await?;
+        }
+        tx.commit().await?;
+
+        Ok(inserted)
+    }
+
+    /// Insert devents in a single `INSERT ... VALUES` statement, at most `INSERT_CHUNK_SIZE` of them
+    async fn insert_chunk<'c, E>(executor: E, devents: &[Devent]) -> Result<u64, Error>
+    where
+        E: Executor<'c, Database = Postgres>,
+    {
+        let mut query_builder: QueryBuilder<Postgres> =
+            QueryBuilder::new(format!("INSERT INTO devents ({}) ", INSERT_COLUMNS.join(", ")));
 
         query_builder.push_values(devents, |mut b, devent| {
             b.push_bind(devent.id)
                 .push_bind(devent.session_id)
+                .push_bind(devent.user_id.clone())
+                .push_bind(devent.client_event_id)
+                .push_bind(devent.seq)
                 .push_bind(devent.mouse_action.clone())
+                .push_bind(devent.mouse_phase)
+                .push_bind(devent.click_count)
                 .push_bind(devent.keyboard_action.clone())
                 .push_bind(devent.scroll_action.clone())
+                .push_bind(devent.window_action)
+                .push_bind(devent.app_context_id)
                 .push_bind(devent.mouse_x)
                 .push_bind(devent.mouse_y)
+                .push_bind(devent.display_id.clone())
                 .push_bind(devent.event_timestamp)
+                .push_bind(devent.event_timestamp_nanos)
+                .push_bind(devent.normalized_timestamp_nanos)
                 .push_bind(devent.deleted_at)
                 .push_bind(devent.created_at)
                 .push_bind(devent.updated_at);
         });
 
-        query_builder.build().execute(pool).await?;
+        query_builder.push(" ON CONFLICT (session_id, client_event_id) DO NOTHING");
+
+        let result = query_builder.build().execute(executor).await?;
 
-        Ok(())
+        Ok(result.rows_affected())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::test_support::database;
+
+    fn devents(session_id: Uuid, count: usize) -> Vec<Devent> {
+        let start = timestamp_nanos(Utc::now());
+        (0..count)
+            .map(|i| Devent {
+                client_event_id: Some(Uuid::new_v4()),
+                seq: Some(i as i64),
+                ..Devent::prepare_for_insert(
+                    session_id,
+                    "user_test_devents".to_string(),
+                    Some(MouseAction::Left),
+                    None,
+                    None,
+                    i as i32,
+                    i as i32,
+                    start + i as i64 * 1_000_000,
+                )
+            })
+            .collect()
+    }
+
+    async fn count_for_session(pool: &PgPool, session_id: Uuid) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM devents WHERE session_id = $1")
+            .bind(session_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn batches_past_the_bind_parameter_limit_are_stored_whole() {
+        let pool = database().await;
+        let session_id = Uuid::new_v4();
+        let batch = devents(session_id, 7_000);
+        assert!(batch.len() * INSERT_COLUMNS.len() > MAX_BIND_PARAMS);
+
+        assert_eq!(Devent::batch_insert(&pool, &batch).await.unwrap(), 7_000);
+        assert_eq!(count_for_session(&pool, session_id).await, 7_000);
+
+        // Retrying stores nothing new, whichever chunk the duplicates fall into
+        assert_eq!(Devent::batch_insert(&pool, &batch).await.unwrap(), 0);
+        assert_eq!(count_for_session(&pool, session_id).await, 7_000);
+    }
+
+    /// Throughput of `batch_insert` against a single `INSERT ... VALUES` statement, which is what
+    /// ingestion used before chunking. Each size is inserted a few times into fresh sessions after a warm
+    /// up, and the median is printed:
+    ///
+    /// `DATABASE_URL=postgres://... cargo test --release batch_insert_throughput -- --ignored --nocapture`
+    #[actix_web::test]
+    #[ignore = "benchmark, needs DATABASE_URL"]
+    async fn batch_insert_throughput() {
+        const RUNS: usize = 5;
+        let pool = database().await;
+
+        fn median(mut timings: Vec<Duration>) -> Duration {
+            timings.sort();
+            timings[timings.len() / 2]
+        }
+
+        Devent::batch_insert(&pool, &devents(Uuid::new_v4(), 100)).await.unwrap();
+        for count in [100, 1_000, INSERT_CHUNK_SIZE, 10_000, 50_000] {
+            let mut single_statement = Vec::new();
+            let mut batch_insert = Vec::new();
+            for _ in 0..RUNS {
+                // Past the bind parameter limit a single statement can't be sent at all
+                if count <= INSERT_CHUNK_SIZE {
+                    let batch = devents(Uuid::new_v4(), count);
+                    let started = Instant::now();
+                    Devent::insert_chunk(&pool, &batch).await.unwrap();
+                    single_statement.push(started.elapsed());
+                }
+
+                let batch = devents(Uuid::new_v4(), count);
+                let started = Instant::now();
+                Devent::batch_insert(&pool, &batch).await.unwrap();
+                batch_insert.push(started.elapsed());
+            }
+
+            for (path, timings) in [("single statement", single_statement), ("batch_insert", batch_insert)] {
+                if timings.is_empty() {
+                    continue;
+                }
+                let elapsed = median(timings);
+                println!(
+                    "{:<16} {:>6} devents: {:>8.1?} ({:.0} devents/s)",
+                    path,
+                    count,
+                    elapsed,
+                    count as f64 / elapsed.as_secs_f64()
+                );
+            }
+        }
+
+        sqlx::query("DELETE FROM devents WHERE user_id = $1")
+            .bind("user_test_devents")
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn keyboard_action_round_trips_through_postgres() {
+        let pool = database().await;
+
+        let keyboard_actions = [
+            KeyboardAction {
+                key: KeyboardActionKey::A,
+                duration: 85,
+                modifiers: vec![KeyboardModifier::Shift, KeyboardModifier::CapsLock],
+                keycode: Some(0),
+                character: Some("A".to_string()),
+            },
+            KeyboardAction {
+                key: KeyboardActionKey::Unknown,
+                duration: 0,
+                modifiers: Vec::new(),
+                keycode: None,
+                character: None,
+            },
+        ];
+
+        for keyboard_action in keyboard_actions {
+            let decoded: KeyboardAction = sqlx::query_scalar("SELECT $1::keyboard_action")
+                .bind(keyboard_action.clone())
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(decoded, keyboard_action);
+        }
+    }
+}
@@ -1,5 +1,20 @@
+pub mod app_contexts;
+pub mod clock_skews;
+pub mod devent_rejections;
+pub mod devent_sequences;
 pub mod devents;
 pub mod recordings;
+pub mod session_displays;
+pub mod sessions;
+pub mod shortcuts;
+pub mod users;
 
+pub use app_contexts::AppContext;
+pub use clock_skews::ClockSkew;
+pub use devent_rejections::DeventRejection;
+pub use devent_sequences::SequenceReport;
 pub use devents::Devent;
 pub use recordings::Recording;
+pub use session_displays::SessionDisplay;
+pub use sessions::Session;
+pub use users::User;
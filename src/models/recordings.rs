@@ -1,17 +1,47 @@
 use anyhow::Result;
 use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{query, FromRow, PgPool};
+use sqlx::{query, Executor, FromRow, PgPool, Postgres, Type};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+/// Where a recording is in its upload, see the `recordings.status` migration
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Type, ToSchema)]
+#[sqlx(type_name = "recording_status_enum", rename_all = "lowercase")] // SQL value name
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum RecordingStatus {
+    /// The upload URL was handed out
+    Pending,
+    /// The client reported the upload done, but the object could not be checked yet
+    Uploaded,
+    /// The object exists with the size the client reported
+    Verified,
+    /// The object is missing or does not match, or the upload never completed
+    Failed,
+}
+
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Recording {
     pub id: Uuid,
     pub session_id: Uuid,
+    pub user_id: Option<String>,
     pub r2_object_key: String,
     pub start_timestamp: DateTime<Utc>,
-    pub duration: u64,
+    /// `start_timestamp` moved onto the server clock in nanoseconds, unset if the client did not send its clock
+    pub normalized_start_timestamp_nanos: Option<i64>,
+    /// Length of the recording in milliseconds
+    pub duration: i32,
+    pub status: RecordingStatus,
+    /// Size of the uploaded object, set once verified. An assembled multipart upload keeps the summed size of
+    /// its parts here while it is uploaded.
+    pub size_bytes: Option<i64>,
+    /// ETag of the uploaded object without quotes, set once verified
+    pub etag: Option<String>,
+    /// Why the recording failed
+    pub failure_reason: Option<String>,
+    pub verified_at: Option<DateTime<Utc>>,
+    /// Multipart upload in progress, or left behind by a failed recording until the sweeper aborts it
+    pub upload_id: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub deleted_at: Option<DateTime<Utc>>,
@@ -22,9 +52,17 @@ impl Default for Recording {
         Recording {
             id: Uuid::new_v4(),
             session_id: Uuid::new_v4(),
+            user_id: None,
             r2_object_key: String::new(),
             start_timestamp: Utc::now(),
+            normalized_start_timestamp_nanos: None,
             duration: 0,
+            status: RecordingStatus::Pending,
+            size_bytes: None,
+            etag: None,
+            failure_reason: None,
+            verified_at: None,
+            upload_id: None,
             deleted_at: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
@@ -33,35 +71,243 @@ impl Default for Recording {
 }
 
 impl Recording {
-    pub async fn new(
-        pool: &PgPool,
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new<'c, E>(
+        executor: E,
         recording_id: Uuid,
         session_id: Uuid,
+        user_id: String,
         r2_object_key: String,
         start_timestamp_nanos: i64,
-        duration_ms: u64,
-    ) -> Result<Self> {
+        normalized_start_timestamp_nanos: Option<i64>,
+        duration_ms: i32,
+    ) -> Result<Self>
+    where
+        E: Executor<'c, Database = Postgres>,
+    {
         let start_timestamp = Utc.timestamp_nanos(start_timestamp_nanos);
 
         let recording = Recording {
             id: recording_id,
             session_id,
+            user_id: Some(user_id),
             r2_object_key,
             start_timestamp,
+            normalized_start_timestamp_nanos,
             duration: duration_ms,
             ..Default::default()
         };
 
         query!(
             r#"
-            INSERT INTO recordings (id, session_id, r2_object_key, start_timestamp, duration, created_at, updated_at) 
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            INSERT INTO recordings (id, session_id, user_id, r2_object_key, start_timestamp, normalized_start_timestamp_nanos, duration, created_at, updated_at) 
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
             "#,
-            recording.id, recording.session_id, recording.r2_object_key, recording.start_timestamp, recording.duration as i64, recording.created_at, recording.updated_at
+            recording.id, recording.session_id, recording.user_id, recording.r2_object_key, recording.start_timestamp, recording.normalized_start_timestamp_nanos, recording.duration, recording.created_at, recording.updated_at
         )
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(recording)
     }
+
+    pub async fn get(pool: &PgPool, id: Uuid) -> Result<Option<Recording>> {
+        let query_str = "SELECT * FROM recordings WHERE id = $1 AND deleted_at IS NULL";
+
+        let recording = sqlx::query_as::<_, Recording>(query_str)
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(recording)
+    }
+
+    /// Every recording of a session, in the order they were started
+    pub async fn get_all_for_session(pool: &PgPool, session_id: Uuid) -> Result<Vec<Recording>> {
+        let query_str = r#"
+            SELECT * FROM recordings
+            WHERE session_id = $1 AND deleted_at IS NULL
+            ORDER BY start_timestamp, id
+        "#;
+
+        let recordings = sqlx::query_as::<_, Recording>(query_str)
+            .bind(session_id)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(recordings)
+    }
+
+    /// The user that uploaded a recording, `None` if the recording does not exist, was deleted or predates
+    /// ownership
+    pub async fn get_owner(pool: &PgPool, id: Uuid) -> Result<Option<String>> {
+        let query_str = "SELECT user_id FROM recordings WHERE id = $1 AND deleted_at IS NULL";
+
+        let owner = sqlx::query_scalar::<_, Option<String>>(query_str)
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(owner.flatten())
+    }
+
+    /// What `/recordings/{id}/complete` needs to know about a recording, `None` if it does not exist or was
+    /// deleted
+    pub async fn get_upload(pool: &PgPool, id: Uuid) -> Result<Option<RecordingUpload>> {
+        let query_str = "SELECT id, user_id, r2_object_key, status, size_bytes, etag, failure_reason, upload_id FROM recordings WHERE id = $1 AND deleted_at IS NULL";
+
+        let upload = sqlx::query_as::<_, RecordingUpload>(query_str)
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(upload)
+    }
+
+    /// Attach the multipart upload the recording is being uploaded with
+    pub async fn start_multipart(pool: &PgPool, id: Uuid, upload_id: &str) -> Result<()> {
+        let query_str = "UPDATE recordings SET upload_id = $2, updated_at = CURRENT_TIMESTAMP WHERE id = $1";
+
+        sqlx::query(query_str).bind(id).bind(upload_id).execute(pool).await?;
+
+        Ok(())
+    }
+
+    /// Note progress on an upload, so the sweeper leaves it alone for another upload timeout
+    pub async fn touch(pool: &PgPool, id: Uuid) -> Result<()> {
+        let query_str = "UPDATE recordings SET updated_at = CURRENT_TIMESTAMP WHERE id = $1";
+
+        sqlx::query(query_str).bind(id).execute(pool).await?;
+
+        Ok(())
+    }
+
+    /// The multipart upload was completed or aborted
+    pub async fn clear_upload_id(pool: &PgPool, id: Uuid) -> Result<()> {
+        let query_str = "UPDATE recordings SET upload_id = NULL, updated_at = CURRENT_TIMESTAMP WHERE id = $1";
+
+        sqlx::query(query_str).bind(id).execute(pool).await?;
+
+        Ok(())
+    }
+
+    /// Storage assembled the multipart upload into the object, which still has to be checked against the
+    /// `size_bytes` of its parts
+    pub async fn finish_multipart(pool: &PgPool, id: Uuid, size_bytes: i64) -> Result<()> {
+        let query_str = r#"
+            UPDATE recordings
+            SET status = 'uploaded', size_bytes = $2, upload_id = NULL, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND status <> 'verified'
+        "#;
+
+        sqlx::query(query_str).bind(id).bind(size_bytes).execute(pool).await?;
+
+        Ok(())
+    }
+
+    /// Failed recordings whose multipart upload still has to be aborted
+    pub async fn get_abandoned_uploads(pool: &PgPool) -> Result<Vec<RecordingUpload>> {
+        let query_str = r#"
+            SELECT id, user_id, r2_object_key, status, size_bytes, etag, failure_reason, upload_id
+            FROM recordings
+            WHERE status = 'failed' AND upload_id IS NOT NULL
+        "#;
+
+        let uploads = sqlx::query_as::<_, RecordingUpload>(query_str)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(uploads)
+    }
+
+    /// The client reported the upload done but the object could not be checked
+    pub async fn mark_uploaded(pool: &PgPool, id: Uuid) -> Result<()> {
+        let query_str = r#"
+            UPDATE recordings
+            SET status = 'uploaded', updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND status <> 'verified'
+        "#;
+
+        sqlx::query(query_str).bind(id).execute(pool).await?;
+
+        Ok(())
+    }
+
+    pub async fn mark_verified(pool: &PgPool, id: Uuid, size_bytes: i64, etag: Option<&str>) -> Result<()> {
+        let query_str = r#"
+            UPDATE recordings
+            SET status = 'verified', size_bytes = $2, etag = $3, failure_reason = NULL, upload_id = NULL,
+                verified_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+        "#;
+
+        sqlx::query(query_str)
+            .bind(id)
+            .bind(size_bytes)
+            .bind(etag)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn mark_failed(pool: &PgPool, id: Uuid, reason: &str) -> Result<()> {
+        let query_str = r#"
+            UPDATE recordings
+            SET status = 'failed', failure_reason = $2, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND status <> 'verified'
+        "#;
+
+        sqlx::query(query_str).bind(id).bind(reason).execute(pool).await?;
+
+        Ok(())
+    }
+
+    /// Pending multipart uploads with no upload progress noted since `idle_since`
+    pub async fn get_stale_multipart_uploads(
+        pool: &PgPool,
+        idle_since: DateTime<Utc>,
+    ) -> Result<Vec<RecordingUpload>> {
+        let query_str = r#"
+            SELECT id, user_id, r2_object_key, status, size_bytes, etag, failure_reason, upload_id
+            FROM recordings
+            WHERE status = 'pending' AND upload_id IS NOT NULL AND updated_at < $1
+        "#;
+
+        let uploads = sqlx::query_as::<_, RecordingUpload>(query_str)
+            .bind(idle_since)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(uploads)
+    }
+
+    /// Fail pending recordings with no upload progress since `idle_since`, returns how many were failed
+    pub async fn fail_stale_pending(pool: &PgPool, idle_since: DateTime<Utc>) -> Result<u64> {
+        let query_str = r#"
+            UPDATE recordings
+            SET status = 'failed', failure_reason = 'upload was never completed', updated_at = CURRENT_TIMESTAMP
+            WHERE status = 'pending' AND updated_at < $1
+        "#;
+
+        let result = sqlx::query(query_str).bind(idle_since).execute(pool).await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// The upload state of a recording
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct RecordingUpload {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub user_id: Option<String>,
+    #[serde(skip)]
+    pub r2_object_key: String,
+    pub status: RecordingStatus,
+    pub size_bytes: Option<i64>,
+    pub etag: Option<String>,
+    pub failure_reason: Option<String>,
+    #[serde(skip)]
+    pub upload_id: Option<String>,
 }
\ No newline at end of file
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, FromRow, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+/// A display attached while a session was recorded. `x`, `y`, `width` and `height` are in the same
+/// logical coordinate space as `Devent::mouse_x`/`mouse_y`, `scale_factor` maps them to physical pixels.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct SessionDisplay {
+    pub session_id: Uuid,
+    pub display_id: String,
+    /// Devents without a `display_id` are placed on the primary display
+    pub is_primary: bool,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub scale_factor: f64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl SessionDisplay {
+    /// Position of a point relative to this display, `(0, 0)` is the top left corner and `(1, 1)` the
+    /// bottom right. Points on another display fall outside of `[0, 1]`. The point and the display are
+    /// both in logical points, so `scale_factor` plays no part here.
+    pub fn normalize(&self, x: i32, y: i32) -> (f64, f64) {
+        (
+            (x as f64 - self.x as f64) / self.width as f64,
+            (y as f64 - self.y as f64) / self.height as f64,
+        )
+    }
+
+    /// Find the display a devent was recorded on, falling back to the primary display of its session
+    pub fn find<'a>(
+        displays: &'a [SessionDisplay],
+        session_id: Uuid,
+        display_id: Option<&str>,
+    ) -> Option<&'a SessionDisplay> {
+        displays.iter().find(|display| {
+            display.session_id == session_id
+                && match display_id {
+                    Some(display_id) => display.display_id == display_id,
+                    None => display.is_primary,
+                }
+        })
+    }
+
+    /// Insert or update the displays of a session, the client sends them again when its setup changes
+    pub async fn upsert_all<'a, A>(conn: A, displays: &[SessionDisplay]) -> Result<()>
+    where
+        A: Acquire<'a, Database = Postgres>,
+    {
+        if displays.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = conn.begin().await?;
+
+        // Only one display of a session can be primary
+        let session_ids: Vec<Uuid> = displays
+            .iter()
+            .filter(|display| display.is_primary)
+            .map(|display| display.session_id)
+            .collect();
+        sqlx::query("UPDATE session_displays SET is_primary = FALSE, updated_at = CURRENT_TIMESTAMP WHERE session_id = ANY($1) AND is_primary")
+            .bind(&session_ids)
+            .execute(&mut *tx)
+            .await?;
+
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO session_displays (session_id, display_id, is_primary, x, y, width, height, scale_factor, created_at, updated_at) ",
+        );
+        query_builder.push_values(displays, |mut b, display| {
+            b.push_bind(display.session_id)
+                .push_bind(display.display_id.clone())
+                .push_bind(display.is_primary)
+                .push_bind(display.x)
+                .push_bind(display.y)
+                .push_bind(display.width)
+                .push_bind(display.height)
+                .push_bind(display.scale_factor)
+                .push_bind(display.created_at)
+                .push_bind(display.updated_at);
+        });
+        query_builder.push(
+            r#"
+            ON CONFLICT (session_id, display_id) DO UPDATE SET
+                is_primary = EXCLUDED.is_primary,
+                x = EXCLUDED.x,
+                y = EXCLUDED.y,
+                width = EXCLUDED.width,
+                height = EXCLUDED.height,
+                scale_factor = EXCLUDED.scale_factor,
+                updated_at = EXCLUDED.updated_at
+            "#,
+        );
+        query_builder.build().execute(&mut *tx).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_all_for_sessions(pool: &PgPool, session_ids: &[Uuid]) -> Result<Vec<SessionDisplay>> {
+        let query_str = "SELECT * FROM session_displays WHERE session_id = ANY($1) ORDER BY session_id, display_id";
+
+        let displays = sqlx::query_as::<_, SessionDisplay>(query_str)
+            .bind(session_ids)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(displays)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(session_id: Uuid, display_id: &str, is_primary: bool, x: i32) -> SessionDisplay {
+        SessionDisplay {
+            session_id,
+            display_id: display_id.to_string(),
+            is_primary,
+            x,
+            y: 0,
+            width: 1440,
+            height: 900,
+            scale_factor: 2.0,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn devents_are_placed_on_their_display_or_the_primary() {
+        let session_id = Uuid::new_v4();
+        let displays = vec![display(session_id, "1", true, 0), display(session_id, "2", false, 1440)];
+
+        let secondary = SessionDisplay::find(&displays, session_id, Some("2")).unwrap();
+        assert_eq!(secondary.normalize(2160, 450), (0.5, 0.5));
+
+        let primary = SessionDisplay::find(&displays, session_id, None).unwrap();
+        assert_eq!(primary.display_id, "1");
+
+        assert!(SessionDisplay::find(&displays, Uuid::new_v4(), None).is_none());
+    }
+
+    #[test]
+    fn points_far_off_the_display_do_not_overflow() {
+        let display = display(Uuid::new_v4(), "1", true, 1440);
+        let (x, _) = display.normalize(i32::MIN, 0);
+        assert!(x < -1_000_000.0);
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, Executor, FromRow, PgPool, Postgres};
+use uuid::Uuid;
+
+/// A recording session of the desktop client, devents and recordings hang off it via `session_id`
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Option<String>,
+    pub app_version: Option<String>,
+    pub os: Option<String>,
+    pub device_id: Option<String>,
+    pub screen_width: Option<i32>,
+    pub screen_height: Option<i32>,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub last_heartbeat_at: DateTime<Utc>,
+    /// Highest sequence number persisted from the WebSocket ingestion channel
+    pub last_ingested_seq: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            id: Uuid::new_v4(),
+            user_id: None,
+            app_version: None,
+            os: None,
+            device_id: None,
+            screen_width: None,
+            screen_height: None,
+            started_at: Utc::now(),
+            ended_at: None,
+            last_heartbeat_at: Utc::now(),
+            last_ingested_seq: 0,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            deleted_at: None,
+        }
+    }
+}
+
+impl Session {
+    pub fn is_open(&self) -> bool {
+        self.ended_at.is_none()
+    }
+
+    /// Insert a session, returns `None` without changing anything if a session with its id already exists
+    pub async fn insert<'c, E>(executor: E, session: &Session) -> Result<Option<Session>>
+    where
+        E: Executor<'c, Database = Postgres>,
+    {
+        let query_str = r#"
+            INSERT INTO sessions (id, user_id, app_version, os, device_id, screen_width, screen_height, started_at, last_heartbeat_at, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (id) DO NOTHING
+            RETURNING *
+            "#;
+
+        let inserted = sqlx::query_as::<_, Session>(query_str)
+            .bind(session.id)
+            .bind(&session.user_id)
+            .bind(&session.app_version)
+            .bind(&session.os)
+            .bind(&session.device_id)
+            .bind(session.screen_width)
+            .bind(session.screen_height)
+            .bind(session.started_at)
+            .bind(session.last_heartbeat_at)
+            .bind(session.created_at)
+            .bind(session.updated_at)
+            .fetch_optional(executor)
+            .await?;
+
+        Ok(inserted)
+    }
+
+    pub async fn get(pool: &PgPool, id: Uuid) -> Result<Option<Session>> {
+        let query_str = "SELECT * FROM sessions WHERE id = $1 AND deleted_at IS NULL";
+
+        let session = sqlx::query_as::<_, Session>(query_str)
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(session)
+    }
+
+    pub async fn get_all_for_user(pool: &PgPool, user_id: &str) -> Result<Vec<Session>> {
+        let query_str = "SELECT * FROM sessions WHERE user_id = $1 AND deleted_at IS NULL ORDER BY started_at DESC";
+
+        let sessions = sqlx::query_as::<_, Session>(query_str)
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(sessions)
+    }
+
+    /// Bump the heartbeat of an open session, returns `None` if the session is not open
+    pub async fn heartbeat(pool: &PgPool, id: Uuid) -> Result<Option<Session>> {
+        let query_str = r#"
+            UPDATE sessions
+            SET last_heartbeat_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND ended_at IS NULL AND deleted_at IS NULL
+            RETURNING *
+            "#;
+
+        let session = sqlx::query_as::<_, Session>(query_str)
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(session)
+    }
+
+    /// End an open session, returns `None` if the session is not open
+    pub async fn end(pool: &PgPool, id: Uuid, ended_at: DateTime<Utc>) -> Result<Option<Session>> {
+        let query_str = r#"
+            UPDATE sessions
+            SET ended_at = $2, last_heartbeat_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND ended_at IS NULL AND deleted_at IS NULL
+            RETURNING *
+            "#;
+
+        let session = sqlx::query_as::<_, Session>(query_str)
+            .bind(id)
+            .bind(ended_at)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(session)
+    }
+
+    /// Highest sequence number persisted from the WebSocket ingestion channel, `None` if the session is no
+    /// longer open. Locks the session row until the transaction ends, so streams writing to the same
+    /// session flush one after another and each sees what the others persisted.
+    pub async fn lock_ingested_seq<'c, E>(executor: E, id: Uuid) -> Result<Option<i64>>
+    where
+        E: Executor<'c, Database = Postgres>,
+    {
+        let last_ingested_seq = query!(
+            r#"
+            SELECT last_ingested_seq FROM sessions
+            WHERE id = $1 AND ended_at IS NULL AND deleted_at IS NULL
+            FOR UPDATE
+            "#,
+            id
+        )
+        .fetch_optional(executor)
+        .await?
+        .map(|row| row.last_ingested_seq);
+
+        Ok(last_ingested_seq)
+    }
+
+    /// Record that devents up to `seq` have been persisted from the WebSocket ingestion channel. Returns
+    /// `false` if the session is no longer open, in which case the caller should roll back.
+    pub async fn advance_ingested_seq<'c, E>(executor: E, id: Uuid, seq: i64) -> Result<bool>
+    where
+        E: Executor<'c, Database = Postgres>,
+    {
+        let result = query!(
+            r#"
+            UPDATE sessions
+            SET last_ingested_seq = GREATEST(last_ingested_seq, $2), last_heartbeat_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND ended_at IS NULL AND deleted_at IS NULL
+            "#,
+            id,
+            seq
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,106 @@
+use serde::Serialize;
+
+use crate::models::devents::{KeyboardAction, KeyboardActionKey, KeyboardModifier};
+
+/// Common keyboard shortcuts, recognised from the key and the modifiers held with it
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shortcut {
+    Copy,
+    Cut,
+    Paste,
+    Undo,
+    Redo,
+    SelectAll,
+    Save,
+    Find,
+    NewTab,
+    CloseWindow,
+    SwitchApp,
+    Screenshot,
+}
+
+impl KeyboardAction {
+    /// Label the chord if it is a common shortcut. Control and Meta both count as the primary modifier,
+    /// so the same shortcut is recognised on macOS, Windows and Linux. Caps lock and fn are ignored.
+    pub fn shortcut(&self) -> Option<Shortcut> {
+        let held = |modifier| self.modifiers.contains(&modifier);
+        let shift = held(KeyboardModifier::Shift);
+        let control = held(KeyboardModifier::Control);
+        let alt = held(KeyboardModifier::Alt);
+        let meta = held(KeyboardModifier::Meta);
+
+        match (&self.key, shift, control, alt, meta) {
+            // Cmd+Tab on macOS, Alt+Tab elsewhere, Shift cycles backwards
+            (KeyboardActionKey::Tab, _, false, true, false) | (KeyboardActionKey::Tab, _, false, false, true) => {
+                Some(Shortcut::SwitchApp)
+            }
+            (KeyboardActionKey::Num3 | KeyboardActionKey::Num4 | KeyboardActionKey::Num5, true, false, false, true) => {
+                Some(Shortcut::Screenshot)
+            }
+            (KeyboardActionKey::PrintScreen, _, _, _, _) => Some(Shortcut::Screenshot),
+            // Everything else needs exactly one of Control and Meta
+            (key, shift, control, false, meta) if control != meta => match (key, shift) {
+                (KeyboardActionKey::C, false) => Some(Shortcut::Copy),
+                (KeyboardActionKey::X, false) => Some(Shortcut::Cut),
+                (KeyboardActionKey::V, false) => Some(Shortcut::Paste),
+                (KeyboardActionKey::Z, false) => Some(Shortcut::Undo),
+                (KeyboardActionKey::Z, true) | (KeyboardActionKey::Y, false) => Some(Shortcut::Redo),
+                (KeyboardActionKey::A, false) => Some(Shortcut::SelectAll),
+                (KeyboardActionKey::S, false) => Some(Shortcut::Save),
+                (KeyboardActionKey::F, false) => Some(Shortcut::Find),
+                (KeyboardActionKey::T, false) => Some(Shortcut::NewTab),
+                (KeyboardActionKey::W, false) => Some(Shortcut::CloseWindow),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(key: KeyboardActionKey, modifiers: &[KeyboardModifier]) -> KeyboardAction {
+        KeyboardAction {
+            key,
+            duration: 80,
+            modifiers: modifiers.to_vec(),
+            keycode: None,
+            character: None,
+        }
+    }
+
+    #[test]
+    fn primary_modifier_shortcuts_on_every_platform() {
+        let mac = chord(KeyboardActionKey::C, &[KeyboardModifier::Meta]);
+        let pc = chord(KeyboardActionKey::C, &[KeyboardModifier::Control, KeyboardModifier::CapsLock]);
+        assert_eq!(mac.shortcut(), Some(Shortcut::Copy));
+        assert_eq!(pc.shortcut(), Some(Shortcut::Copy));
+
+        let redo = chord(KeyboardActionKey::Z, &[KeyboardModifier::Meta, KeyboardModifier::Shift]);
+        assert_eq!(redo.shortcut(), Some(Shortcut::Redo));
+    }
+
+    #[test]
+    fn switch_app_and_screenshots() {
+        let cmd_tab = chord(KeyboardActionKey::Tab, &[KeyboardModifier::Meta]);
+        let alt_shift_tab = chord(KeyboardActionKey::Tab, &[KeyboardModifier::Alt, KeyboardModifier::Shift]);
+        assert_eq!(cmd_tab.shortcut(), Some(Shortcut::SwitchApp));
+        assert_eq!(alt_shift_tab.shortcut(), Some(Shortcut::SwitchApp));
+
+        let cmd_shift_4 = chord(KeyboardActionKey::Num4, &[KeyboardModifier::Meta, KeyboardModifier::Shift]);
+        assert_eq!(cmd_shift_4.shortcut(), Some(Shortcut::Screenshot));
+    }
+
+    #[test]
+    fn other_chords_are_not_labelled() {
+        assert_eq!(chord(KeyboardActionKey::C, &[]).shortcut(), None);
+        assert_eq!(chord(KeyboardActionKey::C, &[KeyboardModifier::Shift]).shortcut(), None);
+        let both = chord(KeyboardActionKey::C, &[KeyboardModifier::Control, KeyboardModifier::Meta]);
+        assert_eq!(both.shortcut(), None);
+        let with_alt = chord(KeyboardActionKey::V, &[KeyboardModifier::Control, KeyboardModifier::Alt]);
+        assert_eq!(with_alt.shortcut(), None);
+    }
+}
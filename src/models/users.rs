@@ -0,0 +1,151 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, FromRow, PgPool, Type};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "role_enum", rename_all = "lowercase")] // SQL value name
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum Role {
+    Admin,
+    Analyst,
+    Member,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Admin => write!(f, "admin"),
+            Role::Analyst => write!(f, "analyst"),
+            Role::Member => write!(f, "member"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "permission_enum")] // SQL value name
+pub enum Permission {
+    #[sqlx(rename = "devents:read_any")]
+    #[serde(rename = "devents:read_any")]
+    DeventsReadAny,
+    #[sqlx(rename = "recordings:read_any")]
+    #[serde(rename = "recordings:read_any")]
+    RecordingsReadAny,
+    #[sqlx(rename = "recordings:download")]
+    #[serde(rename = "recordings:download")]
+    RecordingsDownload,
+    #[sqlx(rename = "roles:manage")]
+    #[serde(rename = "roles:manage")]
+    RolesManage,
+    #[sqlx(rename = "sessions:read_any")]
+    #[serde(rename = "sessions:read_any")]
+    SessionsReadAny,
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Permission::DeventsReadAny => write!(f, "devents:read_any"),
+            Permission::RecordingsReadAny => write!(f, "recordings:read_any"),
+            Permission::RecordingsDownload => write!(f, "recordings:download"),
+            Permission::RolesManage => write!(f, "roles:manage"),
+            Permission::SessionsReadAny => write!(f, "sessions:read_any"),
+        }
+    }
+}
+
+/// Roles granted to a user and the permissions that come with them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserAccess {
+    pub roles: Vec<Role>,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl User {
+    /// Create the user row if it does not exist yet, refreshing the email if we learn a new one
+    pub async fn upsert(pool: &PgPool, id: &str, email: Option<&str>) -> Result<()> {
+        query!(
+            r#"
+            INSERT INTO users (id, email)
+            VALUES ($1, $2)
+            ON CONFLICT (id) DO UPDATE
+            SET email = COALESCE(EXCLUDED.email, users.email), updated_at = CURRENT_TIMESTAMP
+            "#,
+            id,
+            email
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Roles of a user and the permissions they grant, in one query
+    pub async fn get_access(pool: &PgPool, id: &str) -> Result<UserAccess> {
+        let query_str = r#"
+            SELECT ur.role, rp.permission
+            FROM user_roles ur
+            LEFT JOIN role_permissions rp ON rp.role = ur.role
+            WHERE ur.user_id = $1
+            ORDER BY ur.role, rp.permission
+            "#;
+
+        let rows = sqlx::query_as::<_, (Role, Option<Permission>)>(query_str)
+            .bind(id)
+            .fetch_all(pool)
+            .await?;
+
+        let mut access = UserAccess::default();
+        for (role, permission) in rows {
+            if !access.roles.contains(&role) {
+                access.roles.push(role);
+            }
+            if let Some(permission) = permission.filter(|permission| !access.permissions.contains(permission)) {
+                access.permissions.push(permission);
+            }
+        }
+
+        Ok(access)
+    }
+
+    pub async fn grant_role(pool: &PgPool, id: &str, role: Role) -> Result<()> {
+        User::upsert(pool, id, None).await?;
+
+        query!(
+            r#"
+            INSERT INTO user_roles (user_id, role)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id, role) DO NOTHING
+            "#,
+            id,
+            role as Role
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns whether the user actually had the role
+    pub async fn revoke_role(pool: &PgPool, id: &str, role: Role) -> Result<bool> {
+        let result = query!(
+            "DELETE FROM user_roles WHERE user_id = $1 AND role = $2",
+            id,
+            role as Role
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
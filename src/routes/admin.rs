@@ -0,0 +1,228 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use anyhow::Result;
+use std::sync::Arc;
+use tracing::{error, info};
+
+use crate::middleware::auth::AuthorizedUser;
+use crate::models::users::{Permission, Role};
+use crate::models::User;
+use crate::types::{GrantRoleRequest, UserRolesResponse};
+use crate::AppState;
+
+async fn user_roles_response(
+    app_state: &AppState,
+    user_id: String,
+) -> Result<UserRolesResponse, actix_web::Error> {
+    let access = User::get_access(&app_state.pool, &user_id)
+        .await
+        .map_err(|e| {
+            error!("Error getting roles: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(UserRolesResponse {
+        user_id,
+        roles: access.roles,
+        permissions: access.permissions,
+    })
+}
+
+#[get("/users/{user_id}/roles")]
+async fn get_user_roles(
+    app_state: web::Data<Arc<AppState>>,
+    authorized_user: AuthorizedUser,
+    user_id: web::Path<String>,
+) -> Result<web::Json<UserRolesResponse>, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    // Anyone can look up their own roles
+    if user_id == authorized_user.user_id {
+        return Ok(web::Json(UserRolesResponse {
+            user_id,
+            roles: authorized_user.roles,
+            permissions: authorized_user.permissions,
+        }));
+    }
+
+    authorized_user.require(Permission::RolesManage)?;
+
+    let response = user_roles_response(&app_state, user_id).await?;
+
+    Ok(web::Json(response))
+}
+
+#[post("/users/{user_id}/roles")]
+async fn grant_role(
+    app_state: web::Data<Arc<AppState>>,
+    authorized_user: AuthorizedUser,
+    user_id: web::Path<String>,
+    req_body: web::Json<GrantRoleRequest>,
+) -> Result<web::Json<UserRolesResponse>, actix_web::Error> {
+    authorized_user.require(Permission::RolesManage)?;
+
+    let user_id = user_id.into_inner();
+    User::grant_role(&app_state.pool, &user_id, req_body.role)
+        .await
+        .map_err(|e| {
+            error!("Error granting role: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    info!(
+        "User {} granted role {} to {}",
+        authorized_user.user_id, req_body.role, user_id
+    );
+
+    let response = user_roles_response(&app_state, user_id).await?;
+
+    Ok(web::Json(response))
+}
+
+#[delete("/users/{user_id}/roles/{role}")]
+async fn revoke_role(
+    app_state: web::Data<Arc<AppState>>,
+    authorized_user: AuthorizedUser,
+    path: web::Path<(String, Role)>,
+) -> Result<HttpResponse, actix_web::Error> {
+    authorized_user.require(Permission::RolesManage)?;
+
+    let (user_id, role) = path.into_inner();
+    if user_id == authorized_user.user_id && role == Role::Admin {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Admins cannot revoke their own admin role",
+        ));
+    }
+
+    let revoked = User::revoke_role(&app_state.pool, &user_id, role)
+        .await
+        .map_err(|e| {
+            error!("Error revoking role: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    if !revoked {
+        return Err(actix_web::error::ErrorNotFound(format!(
+            "User {} does not have role {}",
+            user_id, role
+        )));
+    }
+
+    info!(
+        "User {} revoked role {} from {}",
+        authorized_user.user_id, role, user_id
+    );
+
+    let response = user_roles_response(&app_state, user_id).await?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::dev::Service;
+    use actix_web::{http::StatusCode, test as actix_test, App};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::test_support::{app_state, authorized_user, database, sign_in_as, signed_in_app, unreachable_pool};
+
+    const ADMIN_PERMISSIONS: &[Permission] = &[
+        Permission::DeventsReadAny,
+        Permission::RecordingsReadAny,
+        Permission::RecordingsDownload,
+        Permission::RolesManage,
+    ];
+
+    async fn call_as(user: AuthorizedUser, req: actix_test::TestRequest) -> (StatusCode, String) {
+        let app = signed_in_app!(app_state(unreachable_pool()), user, get_user_roles, grant_role, revoke_role);
+
+        let res = actix_test::call_service(&app, req.to_request()).await;
+        let status = res.status();
+        let body = actix_test::read_body(res).await;
+        (status, String::from_utf8_lossy(&body).to_string())
+    }
+
+    #[actix_web::test]
+    async fn users_can_read_their_own_roles_only() {
+        let member = authorized_user("user_member", &[Role::Member], &[]);
+
+        let (status, body) =
+            call_as(member.clone(), actix_test::TestRequest::get().uri("/users/user_member/roles")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#""roles":["member"]"#));
+
+        let (status, _) = call_as(member, actix_test::TestRequest::get().uri("/users/user_other/roles")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn granting_and_revoking_needs_roles_manage() {
+        let analyst = authorized_user(
+            "user_analyst",
+            &[Role::Analyst],
+            &[Permission::DeventsReadAny, Permission::RecordingsReadAny, Permission::RecordingsDownload],
+        );
+
+        let grant = actix_test::TestRequest::post()
+            .uri("/users/user_other/roles")
+            .set_json(serde_json::json!({"role": "admin"}));
+        assert_eq!(call_as(analyst.clone(), grant).await.0, StatusCode::FORBIDDEN);
+
+        let revoke = actix_test::TestRequest::delete().uri("/users/user_other/roles/member");
+        assert_eq!(call_as(analyst, revoke).await.0, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn admins_cannot_revoke_their_own_admin_role() {
+        let admin = authorized_user("user_admin", &[Role::Admin], ADMIN_PERMISSIONS);
+
+        let revoke = actix_test::TestRequest::delete().uri("/users/user_admin/roles/admin");
+        assert_eq!(call_as(admin, revoke).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn grant_and_revoke_round_trip() {
+        let pool = database().await;
+        let admin_id = format!("user_test_{}", Uuid::new_v4());
+        let target_id = format!("user_test_{}", Uuid::new_v4());
+        User::grant_role(&pool, &admin_id, Role::Admin).await.unwrap();
+
+        let sign_in_id = admin_id.clone();
+        let app = actix_test::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| {
+                    sign_in_as(&req, &sign_in_id);
+                    srv.call(req)
+                })
+                .app_data(web::Data::new(app_state(pool.clone())))
+                .service(get_user_roles)
+                .service(grant_role)
+                .service(revoke_role),
+        )
+        .await;
+
+        let req = actix_test::TestRequest::post()
+            .uri(&format!("/users/{}/roles", target_id))
+            .set_json(serde_json::json!({"role": "analyst"}))
+            .to_request();
+        let granted: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(granted["roles"], serde_json::json!(["analyst"]));
+        assert_eq!(
+            granted["permissions"],
+            serde_json::json!(["devents:read_any", "recordings:read_any", "recordings:download", "sessions:read_any"])
+        );
+
+        let revoke = || {
+            actix_test::TestRequest::delete()
+                .uri(&format!("/users/{}/roles/analyst", target_id))
+                .to_request()
+        };
+        let res = actix_test::call_service(&app, revoke()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = actix_test::call_service(&app, revoke()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        assert_eq!(User::get_access(&pool, &target_id).await.unwrap(), Default::default());
+    }
+}
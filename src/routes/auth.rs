@@ -4,21 +4,18 @@ use actix_web::{
     Error, Responder,
 };
 use chrono::Utc;
-use futures::stream::{FuturesUnordered, StreamExt};
 use jsonwebtoken::{encode, EncodingKey, Header};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use std::sync::Arc;
-use tokio::sync::Semaphore;
-use tracing::{error, info, warn};
+use tracing::{error, info};
 use utoipa::OpenApi;
 
 use crate::types::{
     AuthCallbackQuery, Claims, GetUserResponse, WorkOSAuthRequest, WorkOSAuthResponse, WorkOSUser,
 };
-use crate::AppState;
-use crate::{middleware::auth::AuthenticatedUser, AppConfig};
+use crate::models::users::{Role, User};
+use crate::{middleware::auth::AuthenticatedUser, AppConfig, AppState};
 
 #[derive(OpenApi)]
 #[openapi(
@@ -52,6 +49,7 @@ async fn signup() -> Result<impl Responder, Error> {
 /// The callback URL for the WorkOS authentication flow for the desktop app
 #[get("/workos/callback")]
 async fn auth_callback(
+    app_state: web::Data<Arc<AppState>>,
     app_config: web::Data<Arc<AppConfig>>,
     info: web::Query<AuthCallbackQuery>,
 ) -> Result<impl Responder, actix_web::Error> {
@@ -61,6 +59,15 @@ async fn auth_callback(
         .await
         .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
 
+    // Make sure the user has a row to hang roles off, everyone starts as a member
+    let user = &auth_response.user;
+    User::upsert(&app_state.pool, &user.id, Some(&user.email))
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    User::grant_role(&app_state.pool, &user.id, Role::Member)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+
     // Sign a JWT with the user info
     let jwt = sign_jwt(&auth_response.user, app_config.get_ref().clone())
         .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
@@ -160,6 +167,7 @@ pub async fn user_id_to_user(
 
 
 /// Look up a user by email using the WorkOS API and return the user information
+#[allow(dead_code)] // Kept for admin tooling, not wired to a route yet
 pub async fn user_email_to_user(
     user_email: &str,
     app_config: Arc<AppConfig>,
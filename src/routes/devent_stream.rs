@@ -0,0 +1,370 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Message};
+use anyhow::Result;
+use chrono::Utc;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::middleware::auth::AuthenticatedUser;
+use crate::models::devents::timestamp_nanos;
+use crate::models::{AppContext, ClockSkew, Devent, Session};
+use crate::routes::sessions::get_writable_session;
+use crate::types::{DeventStreamFrame, DeventStreamMessage};
+use crate::validation::devents::{describe_violations, validate_devent, DeventValidationRules};
+use crate::{AppConfig, AppState};
+
+/// Flush buffered devents once this many are waiting
+const FLUSH_SIZE: usize = 500;
+/// Flush buffered devents at least this often
+const FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+/// Largest frame we accept from the client
+const MAX_FRAME_SIZE: usize = 256 * 1024;
+
+/// WebSocket ingestion channel for live devents of one session.
+///
+/// On connect the server sends `{"type": "ready", "last_seq": n}` and the client sends one
+/// `{"seq": n + 1, "event": {..}}` text frame per devent from there on. Devents are buffered and flushed
+/// through `Devent::batch_insert`, and once a flush commits the server sends `{"type": "ack", "seq": ..}`
+/// for the highest persisted frame. Frames at or below the last persisted sequence number are dropped,
+/// so after a reconnect the client can replay everything it has not seen acked without duplicating.
+///
+/// The frame `seq` is stored as the devent's `seq`, the one sequence the gaps report checks. The event
+/// inside a frame may leave its own `seq` out, a frame whose event sets a different one is rejected.
+///
+/// Binary frames are not supported and close the stream with code 1003.
+///
+/// A frame can also carry `"clock": {..}` now and then, timestamps of that frame and the ones after it
+/// are then stored normalized to the server clock as well.
+#[get("/stream/{session_id}")]
+async fn stream_devents(
+    req: HttpRequest,
+    body: web::Payload,
+    app_state: web::Data<Arc<AppState>>,
+    app_config: web::Data<Arc<AppConfig>>,
+    authenticated_user: AuthenticatedUser,
+    session_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let session = get_writable_session(&app_state.pool, session_id.into_inner(), &authenticated_user.user_id).await?;
+
+    let (response, ws_session, msg_stream) = actix_ws::handle(&req, body)?;
+
+    info!(
+        "User {} opened devent stream for session {} at seq {}",
+        authenticated_user.user_id, session.id, session.last_ingested_seq
+    );
+
+    actix_web::rt::spawn(run_stream(
+        app_state.get_ref().clone(),
+        app_config.devent_validation.clone(),
+        authenticated_user.user_id,
+        session,
+        ws_session,
+        msg_stream.max_frame_size(MAX_FRAME_SIZE),
+    ));
+
+    Ok(response)
+}
+
+struct StreamState {
+    app_state: Arc<AppState>,
+    rules: DeventValidationRules,
+    user_id: String,
+    session_id: Uuid,
+    /// Highest sequence number accepted, persisted or still buffered
+    last_seq: i64,
+    /// Highest sequence number committed to the database
+    persisted_seq: i64,
+    buffer: Vec<Devent>,
+    /// App contexts referenced by the buffered devents
+    app_contexts: HashMap<Uuid, AppContext>,
+    /// Latest client clock sample, used to normalize the timestamps of the following frames
+    clock_skew: Option<ClockSkew>,
+    /// Clock samples taken since the last flush
+    clock_skews: Vec<ClockSkew>,
+}
+
+impl StreamState {
+    fn accept(&mut self, frame: DeventStreamFrame) -> Result<bool, String> {
+        if frame.event.session_id != self.session_id {
+            return Err(format!(
+                "Event is for session {}, this stream is for {}",
+                frame.event.session_id, self.session_id
+            ));
+        }
+
+        if frame.event.seq.is_some_and(|seq| seq != frame.seq) {
+            return Err(format!("Event seq does not match frame seq {}", frame.seq));
+        }
+
+        if frame.seq <= self.last_seq {
+            // Replayed after a reconnect, we already have it
+            return Ok(false);
+        }
+
+        // Invalid frames are reported and skipped without advancing the sequence, so the client
+        // can fix and resend them under the same number
+        if let Err(violations) = validate_devent(&frame.event, &self.rules, Utc::now()) {
+            return Err(format!("Invalid devent: {}", describe_violations(&violations)));
+        }
+
+        if frame.seq != self.last_seq + 1 {
+            warn!(
+                "Devent stream for session {} skipped from seq {} to {}",
+                self.session_id, self.last_seq, frame.seq
+            );
+        }
+
+        if let Some(app_context) = frame.event.app_context() {
+            self.app_contexts.entry(app_context.id).or_insert(app_context);
+        }
+        if let Some(clock) = &frame.clock {
+            let clock_skew = clock.to_clock_skew(self.session_id, self.user_id.clone(), timestamp_nanos(Utc::now()));
+            self.clock_skews.push(clock_skew.clone());
+            self.clock_skew = Some(clock_skew);
+        }
+        let mut devent = frame.event.to_devent(self.user_id.clone(), self.clock_skew.as_ref());
+        devent.seq = Some(frame.seq);
+        self.buffer.push(devent);
+        self.last_seq = frame.seq;
+        Ok(true)
+    }
+
+    /// Persist the buffer and the sequence number it reaches in one transaction, returning the seq to ack.
+    /// Another stream for the session may have persisted some of the buffered frames already, those are
+    /// dropped here rather than stored twice.
+    async fn flush(&mut self) -> Result<Option<i64>> {
+        if self.buffer.is_empty() {
+            return Ok(None);
+        }
+
+        let mut tx = self.app_state.pool.begin().await?;
+        let Some(persisted_seq) = Session::lock_ingested_seq(&mut *tx, self.session_id).await? else {
+            tx.rollback().await?;
+            return Err(anyhow::anyhow!("Session {} has ended", self.session_id));
+        };
+        self.buffer.retain(|devent| devent.seq.is_some_and(|seq| seq > persisted_seq));
+        self.last_seq = self.last_seq.max(persisted_seq);
+
+        let app_contexts: Vec<AppContext> = self.app_contexts.values().cloned().collect();
+        AppContext::insert_missing(&mut *tx, &app_contexts).await?;
+        Devent::batch_insert(&mut *tx, &self.buffer).await?;
+        ClockSkew::batch_insert(&mut *tx, &self.clock_skews).await?;
+        if !Session::advance_ingested_seq(&mut *tx, self.session_id, self.last_seq).await? {
+            tx.rollback().await?;
+            return Err(anyhow::anyhow!("Session {} has ended", self.session_id));
+        }
+        tx.commit().await?;
+
+        self.buffer.clear();
+        self.app_contexts.clear();
+        self.clock_skews.clear();
+        self.persisted_seq = self.last_seq;
+        Ok(Some(self.persisted_seq))
+    }
+}
+
+async fn send(ws_session: &mut actix_ws::Session, message: &DeventStreamMessage) -> bool {
+    match serde_json::to_string(message) {
+        Ok(text) => ws_session.text(text).await.is_ok(),
+        Err(e) => {
+            error!("Error serializing devent stream message: {:?}", e);
+            false
+        }
+    }
+}
+
+async fn run_stream(
+    app_state: Arc<AppState>,
+    rules: DeventValidationRules,
+    user_id: String,
+    session: Session,
+    mut ws_session: actix_ws::Session,
+    mut msg_stream: actix_ws::MessageStream,
+) {
+    let mut state = StreamState {
+        app_state,
+        rules,
+        user_id,
+        session_id: session.id,
+        last_seq: session.last_ingested_seq,
+        persisted_seq: session.last_ingested_seq,
+        buffer: Vec::with_capacity(FLUSH_SIZE),
+        app_contexts: HashMap::new(),
+        clock_skew: None,
+        clock_skews: Vec::new(),
+    };
+
+    if !send(&mut ws_session, &DeventStreamMessage::Ready { last_seq: state.last_seq }).await {
+        return;
+    }
+
+    let mut flush_interval = tokio::time::interval(FLUSH_INTERVAL);
+    let mut close_reason = None;
+
+    loop {
+        let should_flush = tokio::select! {
+            _ = flush_interval.tick() => true,
+            msg = msg_stream.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    let seq = serde_json::from_str::<serde_json::Value>(&text)
+                        .ok()
+                        .and_then(|value| value.get("seq").and_then(|seq| seq.as_i64()));
+                    let accepted = serde_json::from_str::<DeventStreamFrame>(&text)
+                        .map_err(|e| format!("Invalid frame: {}", e))
+                        .and_then(|frame| state.accept(frame));
+
+                    match accepted {
+                        Ok(true) => state.buffer.len() >= FLUSH_SIZE,
+                        Ok(false) => {
+                            // Let a replaying client know where we are
+                            if !send(&mut ws_session, &DeventStreamMessage::Ack { seq: state.persisted_seq }).await {
+                                break;
+                            }
+                            false
+                        }
+                        Err(message) => {
+                            if !send(&mut ws_session, &DeventStreamMessage::Error { seq, message }).await {
+                                break;
+                            }
+                            false
+                        }
+                    }
+                }
+                Some(Ok(Message::Ping(bytes))) => {
+                    if ws_session.pong(&bytes).await.is_err() {
+                        break;
+                    }
+                    false
+                }
+                Some(Ok(Message::Binary(_))) => {
+                    warn!("Devent stream for session {} sent a binary frame", state.session_id);
+                    close_reason = Some(CloseReason {
+                        code: CloseCode::Unsupported,
+                        description: Some("Devent frames must be sent as text".to_string()),
+                    });
+                    break;
+                }
+                Some(Ok(Message::Close(reason))) => {
+                    close_reason = reason;
+                    break;
+                }
+                Some(Ok(_)) => false,
+                Some(Err(e)) => {
+                    warn!("Devent stream protocol error for session {}: {:?}", state.session_id, e);
+                    close_reason = Some(CloseReason::from(CloseCode::Protocol));
+                    break;
+                }
+                None => break,
+            }
+        };
+
+        if should_flush {
+            match state.flush().await {
+                Ok(Some(seq)) => {
+                    if !send(&mut ws_session, &DeventStreamMessage::Ack { seq }).await {
+                        break;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Error flushing devent stream for session {}: {:?}", state.session_id, e);
+                    let _ = send(
+                        &mut ws_session,
+                        &DeventStreamMessage::Error { seq: None, message: e.to_string() },
+                    )
+                    .await;
+                    close_reason = Some(CloseReason::from(CloseCode::Error));
+                    // Whatever was buffered is lost, the client replays it from the last ack
+                    state.buffer.clear();
+                    state.app_contexts.clear();
+                    break;
+                }
+            }
+        }
+    }
+
+    // Persist whatever is left, the client will not see the ack but won't duplicate on replay either
+    if let Err(e) = state.flush().await {
+        error!("Error flushing devent stream for session {} on close: {:?}", state.session_id, e);
+    }
+
+    info!("Devent stream for session {} closed at seq {}", state.session_id, state.persisted_seq);
+    let _ = ws_session.close(close_reason).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app_state, database};
+
+    fn frame(session_id: Uuid, seq: i64) -> DeventStreamFrame {
+        serde_json::from_value(serde_json::json!({
+            "seq": seq,
+            "event": {
+                "session_id": session_id,
+                "mouse_action": "left",
+                "mouse_x": 10,
+                "mouse_y": 20,
+                "event_timestamp_nanos": timestamp_nanos(Utc::now()),
+            },
+        }))
+        .unwrap()
+    }
+
+    fn stream_state(app_state: Arc<AppState>, session: &Session) -> StreamState {
+        StreamState {
+            app_state,
+            rules: DeventValidationRules::default(),
+            user_id: session.user_id.clone().unwrap(),
+            session_id: session.id,
+            last_seq: session.last_ingested_seq,
+            persisted_seq: session.last_ingested_seq,
+            buffer: Vec::new(),
+            app_contexts: HashMap::new(),
+            clock_skew: None,
+            clock_skews: Vec::new(),
+        }
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn two_streams_for_one_session_store_each_frame_once() {
+        let pool = database().await;
+        let session = Session {
+            user_id: Some(format!("user_test_{}", Uuid::new_v4())),
+            ..Default::default()
+        };
+        Session::insert(&pool, &session).await.unwrap();
+        let app_state = app_state(pool.clone());
+
+        // Both connected before either flushed, so both start from seq 0
+        let mut first = stream_state(app_state.clone(), &session);
+        let mut second = stream_state(app_state, &session);
+        for seq in 1..=3 {
+            assert_eq!(first.accept(frame(session.id, seq)), Ok(true));
+        }
+        for seq in 1..=4 {
+            assert_eq!(second.accept(frame(session.id, seq)), Ok(true));
+        }
+
+        assert_eq!(first.flush().await.unwrap(), Some(3));
+        assert_eq!(second.flush().await.unwrap(), Some(4));
+
+        // Frame 4 is already stored by the second stream, the first drops it when flushing
+        assert_eq!(first.accept(frame(session.id, 4)), Ok(true));
+        assert_eq!(first.flush().await.unwrap(), Some(4));
+        assert_eq!(first.accept(frame(session.id, 4)), Ok(false));
+
+        let seqs: Vec<Option<i64>> =
+            sqlx::query_scalar("SELECT seq FROM devents WHERE session_id = $1 ORDER BY seq")
+                .bind(session.id)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(seqs, vec![Some(1), Some(2), Some(3), Some(4)]);
+    }
+}
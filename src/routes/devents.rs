@@ -1,19 +1,210 @@
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::dev::{Decompress, Payload};
+use actix_web::http::header;
+use actix_web::{get, middleware::Compress, post, web, FromRequest, HttpMessage, HttpRequest, HttpResponse};
 use anyhow::Result;
+use chrono::Utc;
+use futures::future::LocalBoxFuture;
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use crate::models::Devent;
-use crate::types::{DeventRequest, DeventRequestWrapper};
-use crate::{middleware::auth::AuthenticatedUser, AppState};
+use crate::models::devents::{timestamp_nanos, DeventCursor, DeventPageFilter, RecordingDevent};
+use crate::models::users::Permission;
+use crate::models::{AppContext, ClockSkew, Devent, DeventRejection, Recording, SequenceReport, SessionDisplay};
+use crate::types::{
+    CreateDeventsResponse, DeventPage, DeventPageQuery, DeventRequest, DeventRequestWrapper, PositionedDevent,
+    RejectedDevent,
+};
+use crate::middleware::auth::{AuthenticatedUser, AuthorizedUser};
+use crate::routes::sessions::{get_readable_session, get_writable_session};
+use crate::validation::devents::{describe_violations, validate_devent};
+use crate::config::DEFAULT_MAX_DEVENT_BATCH_BYTES;
+use crate::{AppConfig, AppState};
 
+const DEFAULT_PAGE_LIMIT: i64 = 1000;
+const MAX_PAGE_LIMIT: i64 = 10000;
+
+/// Validate the paging query parameters shared by the devent listing routes
+fn page_filter(query: DeventPageQuery) -> Result<DeventPageFilter, actix_web::Error> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_LIMIT
+        )));
+    }
+
+    let after = query
+        .cursor
+        .as_deref()
+        .map(DeventCursor::decode)
+        .transpose()
+        .map_err(|e| actix_web::error::ErrorBadRequest(format!("Invalid cursor: {}", e)))?;
+
+    Ok(DeventPageFilter {
+        from: query.from,
+        to: query.to,
+        after,
+        bundle_id: query.app,
+        limit,
+    })
+}
+
+/// Attach the position of each devent on its display when the caller asked for it
+async fn position_devents<T>(
+    pool: &PgPool,
+    devents: Vec<T>,
+    normalize: bool,
+    devent_of: impl Fn(&T) -> &Devent,
+) -> Result<Vec<PositionedDevent<T>>, actix_web::Error> {
+    let displays = if normalize {
+        let session_ids: HashSet<Uuid> = devents.iter().map(|item| devent_of(item).session_id).collect();
+        let session_ids: Vec<Uuid> = session_ids.into_iter().collect();
+        SessionDisplay::get_all_for_sessions(pool, &session_ids)
+            .await
+            .map_err(|e| {
+                error!("Error getting session displays: {:?}", e);
+                actix_web::error::ErrorInternalServerError(e)
+            })?
+    } else {
+        Vec::new()
+    };
+
+    Ok(devents
+        .into_iter()
+        .map(|item| {
+            let devent = devent_of(&item);
+            let position = SessionDisplay::find(&displays, devent.session_id, devent.display_id.as_deref())
+                .map(|display| display.normalize(devent.mouse_x, devent.mouse_y));
+
+            PositionedDevent {
+                normalized_x: position.map(|(x, _)| x),
+                normalized_y: position.map(|(_, y)| y),
+                devent: item,
+            }
+        })
+        .collect())
+}
+
+/// Encodings of a devent batch body, picked by `Content-Type`. Both carry the `DeventRequestWrapper`
+/// schema, MessagePack bodies encode UUIDs as strings like JSON does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DeventBatchFormat {
+    Json,
+    MessagePack,
+}
+
+impl DeventBatchFormat {
+    fn from_request(req: &HttpRequest) -> Result<Self, actix_web::Error> {
+        let mime = req
+            .mime_type()
+            .map_err(actix_web::error::ErrorBadRequest)?
+            .ok_or_else(|| actix_web::error::ErrorUnsupportedMediaType("Missing Content-Type"))?;
+
+        match (mime.type_().as_str(), mime.subtype().as_str(), mime.suffix().map(|s| s.as_str())) {
+            ("application", "json", _) | ("application", _, Some("json")) => Ok(DeventBatchFormat::Json),
+            ("application", "msgpack" | "x-msgpack" | "vnd.msgpack", _) => Ok(DeventBatchFormat::MessagePack),
+            _ => Err(actix_web::error::ErrorUnsupportedMediaType(format!(
+                "Unsupported Content-Type {}, send application/json or application/msgpack",
+                mime
+            ))),
+        }
+    }
+
+    fn decode(self, body: &[u8]) -> Result<DeventRequestWrapper, String> {
+        match self {
+            DeventBatchFormat::Json => serde_json::from_slice(body).map_err(|e| e.to_string()),
+            DeventBatchFormat::MessagePack => {
+                let mut deserializer = rmp_serde::Deserializer::new(body).with_human_readable();
+                DeventRequestWrapper::deserialize(&mut deserializer).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+/// Body of `/devents/create`. Accepts JSON or MessagePack, optionally compressed with any
+/// `Content-Encoding` actix supports (gzip, zstd, br). The size limit applies to the decompressed body.
+struct DeventBatch(DeventRequestWrapper);
+
+impl FromRequest for DeventBatch {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<DeventBatch, actix_web::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let format = DeventBatchFormat::from_request(req);
+        let limit = req
+            .app_data::<web::Data<Arc<AppConfig>>>()
+            .map(|app_config| app_config.max_devent_batch_bytes)
+            .unwrap_or(DEFAULT_MAX_DEVENT_BATCH_BYTES);
+        let content_length = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse::<usize>().ok());
+        let mut stream = Decompress::from_headers(payload.take(), req.headers());
+
+        Box::pin(async move {
+            let format = format?;
+            let too_large = || actix_web::error::ErrorPayloadTooLarge(format!("Devent batch is larger than {} bytes", limit));
+
+            // Content-Length is the size on the wire, if that alone is over the limit reject before reading
+            if content_length.is_some_and(|length| length > limit) {
+                return Err(too_large());
+            }
+
+            let mut body = web::BytesMut::new();
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                if body.len() + chunk.len() > limit {
+                    return Err(too_large());
+                }
+                body.extend_from_slice(&chunk);
+            }
+
+            format
+                .decode(&body)
+                .map(DeventBatch)
+                .map_err(|e| actix_web::error::ErrorBadRequest(format!("Invalid devent batch: {}", e)))
+        })
+    }
+}
+
+/// Store the accepted devents with the app contexts they reference, the dead letters and the clock samples
+/// of one batch in a single transaction. Returns how many devents were inserted and the sequence issues
+/// found, checked in that same transaction.
+async fn store_batch(
+    pool: &PgPool,
+    app_contexts: &[AppContext],
+    devents: &[Devent],
+    rejections: &[DeventRejection],
+    clock_skews: &[ClockSkew],
+) -> Result<(u64, HashMap<Uuid, SequenceReport>)> {
+    let mut tx = pool.begin().await?;
+    let sequence_issues = check_sequences(&mut tx, devents).await?;
+    AppContext::insert_missing(&mut *tx, app_contexts).await?;
+    let accepted = Devent::batch_insert(&mut *tx, devents).await?;
+    DeventRejection::batch_insert(&mut *tx, rejections).await?;
+    ClockSkew::batch_insert(&mut *tx, clock_skews).await?;
+    tx.commit().await?;
+
+    Ok((accepted, sequence_issues))
+}
+
+/// Store a batch of devents. Events that can't be parsed, fail validation or point at a session the caller
+/// can't write to are rejected one by one: the rest of the batch is still stored, the rejected events go to
+/// the `devent_rejections` table and the response lists them by their index in the batch.
 #[post("/create")]
 async fn create_devent(
     app_state: web::Data<Arc<AppState>>,
-    //_authenticated_user: AuthenticatedUser,
-    req_body: web::Json<DeventRequestWrapper>,    
-) -> Result<HttpResponse, actix_web::Error> {
+    app_config: web::Data<Arc<AppConfig>>,
+    authenticated_user: AuthenticatedUser,
+    req_body: DeventBatch,
+) -> Result<web::Json<CreateDeventsResponse>, actix_web::Error> {
+    let DeventBatch(req_body) = req_body;
     info!("Received create_devent request with {} events", req_body.events.len());
 
     if req_body.events.is_empty() {
@@ -21,42 +212,132 @@ async fn create_devent(
         return Err(actix_web::error::ErrorBadRequest("Empty request body"));
     }
 
-    let devents: Vec<Devent> = req_body.events.iter().map(|devent_request| {
-        Devent::prepare_for_insert(
-            devent_request.session_id,
-            devent_request.mouse_action.clone(),
-            devent_request.keyboard_action.clone(),
-            devent_request.scroll_action.clone(),
-            devent_request.mouse_x,
-            devent_request.mouse_y,
-            devent_request.event_timestamp_nanos
-        )
-    }).collect();
-
-    match Devent::batch_insert(&app_state.pool, &devents).await {
-        Ok(_) => {
-            info!("Successfully created all devents");
-            Ok(HttpResponse::Ok().json("Successfully created devents"))
-        },
-        Err(e) => {
+    let user_id = &authenticated_user.user_id;
+    let now = Utc::now();
+    let mut rejections = Vec::new();
+    let mut valid = Vec::with_capacity(req_body.events.len());
+
+    for (index, payload) in req_body.events.into_iter().enumerate() {
+        let checked = DeventRequest::deserialize(&payload)
+            .map_err(|e| format!("invalid event: {}", e))
+            .and_then(|devent| {
+                validate_devent(&devent, &app_config.devent_validation, now)
+                    .map(|_| devent)
+                    .map_err(|violations| describe_violations(&violations))
+            });
+
+        match checked {
+            Ok(devent) => valid.push((index, devent, payload)),
+            Err(reason) => rejections.push(DeventRejection::new(user_id.clone(), index, payload, reason)),
+        }
+    }
+
+    // Only write into open sessions that belong to the caller
+    let mut session_errors: HashMap<Uuid, Option<String>> = HashMap::new();
+    for (_, devent, _) in &valid {
+        if session_errors.contains_key(&devent.session_id) {
+            continue;
+        }
+        let session_error = match get_writable_session(&app_state.pool, devent.session_id, user_id).await {
+            Ok(_) => None,
+            Err(e) if e.as_response_error().status_code().is_client_error() => Some(e.to_string()),
+            Err(e) => return Err(e),
+        };
+        session_errors.insert(devent.session_id, session_error);
+    }
+
+    // One clock sample per session the batch writes into
+    let received_at_nanos = timestamp_nanos(now);
+    let mut clock_skews = HashMap::new();
+    let mut devents = Vec::with_capacity(valid.len());
+    let mut app_contexts = HashMap::new();
+    for (index, devent, payload) in valid {
+        match &session_errors[&devent.session_id] {
+            Some(reason) => rejections.push(DeventRejection::new(user_id.clone(), index, payload, reason.clone())),
+            None => {
+                if let Some(app_context) = devent.app_context() {
+                    app_contexts.entry(app_context.id).or_insert(app_context);
+                }
+                let clock_skew = req_body.clock.as_ref().map(|clock| {
+                    &*clock_skews.entry(devent.session_id).or_insert_with(|| {
+                        clock.to_clock_skew(devent.session_id, user_id.clone(), received_at_nanos)
+                    })
+                });
+                devents.push(devent.to_devent(user_id.clone(), clock_skew));
+            }
+        }
+    }
+    let app_contexts: Vec<AppContext> = app_contexts.into_values().collect();
+    let clock_skews: Vec<ClockSkew> = clock_skews.into_values().collect();
+    rejections.sort_by_key(|rejection| rejection.batch_index);
+
+    let (accepted, sequence_issues) = store_batch(&app_state.pool, &app_contexts, &devents, &rejections, &clock_skews)
+        .await
+        .map_err(|e| {
             error!("Error creating devents: {:?}", e);
-            Err(actix_web::error::ErrorInternalServerError(e))
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    let duplicates = devents.len() as u64 - accepted;
+    for (session_id, report) in &sequence_issues {
+        warn!(
+            "Devents for session {} have {} missing, {} duplicate and {} reordered sequence numbers",
+            session_id, report.missing_count, report.duplicate_count, report.reordering_count
+        );
+    }
+    info!(
+        "Created {} devents, skipped {} duplicates, rejected {}",
+        accepted,
+        duplicates,
+        rejections.len()
+    );
+
+    Ok(web::Json(CreateDeventsResponse {
+        accepted,
+        duplicates,
+        rejected: rejections
+            .into_iter()
+            .map(|rejection| RejectedDevent {
+                index: rejection.batch_index as usize,
+                reason: rejection.reason,
+            })
+            .collect(),
+        sequence_issues,
+    }))
+}
+
+/// Check the `seq` numbers of a batch per session, in the order they were sent, against the highest
+/// one already stored. Only sessions with issues are returned. Sessions are locked in id order so two
+/// batches spanning the same sessions can't deadlock.
+async fn check_sequences(
+    tx: &mut Transaction<'_, Postgres>,
+    devents: &[Devent],
+) -> Result<HashMap<Uuid, SequenceReport>> {
+    let mut seqs: BTreeMap<Uuid, Vec<i64>> = BTreeMap::new();
+    for devent in devents {
+        if let Some(seq) = devent.seq {
+            seqs.entry(devent.session_id).or_default().push(seq);
+        }
+    }
+
+    let mut issues = HashMap::new();
+    for (session_id, seqs) in seqs {
+        let last_seq = SequenceReport::last_seq_for_session(&mut **tx, session_id).await?;
+        let report = SequenceReport::analyze(last_seq, seqs);
+        if !report.is_clean() {
+            issues.insert(session_id, report);
         }
     }
+
+    Ok(issues)
 }
 
 #[get("/{id}")]
 async fn get_devent(
     app_state: web::Data<Arc<AppState>>,
-    authenticated_user: AuthenticatedUser,
+    authorized_user: AuthorizedUser,
     id: web::Path<Uuid>,
 ) -> Result<web::Json<Devent>, actix_web::Error> {
-    if !authenticated_user.is_admin() {
-        return Err(actix_web::error::ErrorUnauthorized(
-            "Unauthorized".to_string(),
-        ));
-    }
-
     let devent = Devent::get(&app_state.pool, id.into_inner())
         .await
         .map_err(|e|{
@@ -64,49 +345,481 @@ async fn get_devent(
             actix_web::error::ErrorInternalServerError(e)
         })?;
 
+    if devent.user_id.as_ref() != Some(&authorized_user.user_id) {
+        authorized_user.require(Permission::DeventsReadAny)?;
+    }
+
     Ok(web::Json(devent))
 }
 
 #[get("/session/{session_id}")]
 async fn get_devents_for_session(
     app_state: web::Data<Arc<AppState>>,
-    authenticated_user: AuthenticatedUser,
+    authorized_user: AuthorizedUser,
     session_id: web::Path<Uuid>,
-) -> Result<web::Json<Vec<Devent>>, actix_web::Error> {
-    if !authenticated_user.is_admin() {
-        return Err(actix_web::error::ErrorUnauthorized(
-            "Unauthorized".to_string(),
-        ));
-    }
+    query: web::Query<DeventPageQuery>,
+) -> Result<web::Json<DeventPage<PositionedDevent<Devent>>>, actix_web::Error> {
+    let session_id = session_id.into_inner();
+    let query = query.into_inner();
+    let normalize = query.normalize;
+    let filter = page_filter(query)?;
+
+    get_readable_session(&app_state.pool, session_id, &authorized_user, Permission::DeventsReadAny).await?;
 
-    let devents = Devent::get_all_for_session(&app_state.pool, session_id.into_inner())
+    let (devents, next_cursor) = Devent::get_page_for_session(&app_state.pool, session_id, &filter)
         .await
         .map_err(|e|{
             error!("Error getting devents: {:?}", e);
             actix_web::error::ErrorInternalServerError(e)
         })?;
 
-    Ok(web::Json(devents))
+    Ok(web::Json(DeventPage {
+        data: position_devents(&app_state.pool, devents, normalize, |devent| devent).await?,
+        next_cursor: next_cursor.map(|cursor| cursor.encode()),
+    }))
+}
+
+/// Sequence numbers of a session's devents that are missing, repeated or out of timestamp order
+#[get("/session/{session_id}/gaps")]
+async fn get_sequence_gaps_for_session(
+    app_state: web::Data<Arc<AppState>>,
+    authorized_user: AuthorizedUser,
+    session_id: web::Path<Uuid>,
+) -> Result<web::Json<SequenceReport>, actix_web::Error> {
+    let session_id = session_id.into_inner();
+    get_readable_session(&app_state.pool, session_id, &authorized_user, Permission::DeventsReadAny).await?;
+
+    let report = SequenceReport::for_session(&app_state.pool, session_id)
+        .await
+        .map_err(|e| {
+            error!("Error checking devent sequence: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(report))
+}
+
+/// Applications and windows that received devents of a session, to resolve `app_context_id`
+#[get("/session/{session_id}/apps")]
+async fn get_app_contexts_for_session(
+    app_state: web::Data<Arc<AppState>>,
+    authorized_user: AuthorizedUser,
+    session_id: web::Path<Uuid>,
+) -> Result<web::Json<Vec<AppContext>>, actix_web::Error> {
+    let session_id = session_id.into_inner();
+
+    get_readable_session(&app_state.pool, session_id, &authorized_user, Permission::DeventsReadAny).await?;
+
+    let app_contexts = AppContext::get_all_for_session(&app_state.pool, session_id)
+        .await
+        .map_err(|e| {
+            error!("Error getting app contexts: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(app_contexts))
 }
 
 #[get("/recording/{recording_id}")]
 async fn get_devents_for_recording(
     app_state: web::Data<Arc<AppState>>,
-    authenticated_user: AuthenticatedUser,
+    authorized_user: AuthorizedUser,
     recording_id: web::Path<Uuid>,
-) -> Result<web::Json<Vec<Devent>>, actix_web::Error> {
-    if !authenticated_user.is_admin() {
-        return Err(actix_web::error::ErrorUnauthorized(
-            "Unauthorized".to_string(),
-        ));
+    query: web::Query<DeventPageQuery>,
+) -> Result<web::Json<DeventPage<PositionedDevent<RecordingDevent>>>, actix_web::Error> {
+    let recording_id = recording_id.into_inner();
+    let query = query.into_inner();
+    let normalize = query.normalize;
+    let filter = page_filter(query)?;
+
+    if !authorized_user.has_permission(Permission::DeventsReadAny) {
+        let owner = Recording::get_owner(&app_state.pool, recording_id)
+            .await
+            .map_err(|e| {
+                error!("Error getting recording owner: {:?}", e);
+                actix_web::error::ErrorInternalServerError(e)
+            })?;
+
+        if owner.as_ref() != Some(&authorized_user.user_id) {
+            authorized_user.require(Permission::DeventsReadAny)?;
+        }
     }
 
-    let devents = Devent::get_all_for_recording(&app_state.pool, recording_id.into_inner())
+    let (devents, next_cursor) = Devent::get_page_for_recording(&app_state.pool, recording_id, &filter)
         .await
         .map_err(|e|{
             error!("Error getting devents: {:?}", e);
             actix_web::error::ErrorInternalServerError(e)
         })?;
 
-    Ok(web::Json(devents))
-}
\ No newline at end of file
+    Ok(web::Json(DeventPage {
+        data: position_devents(&app_state.pool, devents, normalize, |recording_devent| &recording_devent.devent).await?,
+        next_cursor: next_cursor.map(|cursor| cursor.encode()),
+    }))
+}
+
+/// Channel capacity between the database stream and the response, in chunks
+const EXPORT_CHANNEL_CAPACITY: usize = 16;
+/// Rows are written to the response in chunks of roughly this many bytes
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Export every devent of a session as newline delimited JSON. Rows are streamed straight from Postgres
+/// so the export runs in constant memory, and the response is gzipped if the client sends
+/// `Accept-Encoding: gzip`.
+#[get("/session/{session_id}/export", wrap = "Compress::default()")]
+async fn export_devents_for_session(
+    app_state: web::Data<Arc<AppState>>,
+    authorized_user: AuthorizedUser,
+    session_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let session_id = session_id.into_inner();
+
+    get_readable_session(&app_state.pool, session_id, &authorized_user, Permission::DeventsReadAny).await?;
+
+    info!("User {} exporting devents for session {}", authorized_user.user_id, session_id);
+
+    let (mut tx, rx) = mpsc::channel::<Result<web::Bytes, actix_web::Error>>(EXPORT_CHANNEL_CAPACITY);
+    let app_state = app_state.into_inner();
+
+    actix_web::rt::spawn(async move {
+        let mut devents = Devent::stream_for_session(&app_state.pool, session_id);
+        let mut chunk = Vec::with_capacity(EXPORT_CHUNK_SIZE);
+
+        while let Some(devent) = devents.next().await {
+            let line = devent
+                .map_err(actix_web::error::ErrorInternalServerError)
+                .and_then(|devent| serde_json::to_vec(&devent).map_err(actix_web::error::ErrorInternalServerError));
+
+            match line {
+                Ok(line) => {
+                    chunk.extend_from_slice(&line);
+                    chunk.push(b'\n');
+                }
+                Err(e) => {
+                    error!("Error exporting devents for session {}: {:?}", session_id, e);
+                    // Erroring the body aborts the response so the client can tell the export is incomplete
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            }
+
+            if chunk.len() >= EXPORT_CHUNK_SIZE {
+                let full = std::mem::replace(&mut chunk, Vec::with_capacity(EXPORT_CHUNK_SIZE));
+                if tx.send(Ok(web::Bytes::from(full))).await.is_err() {
+                    info!("Client went away while exporting session {}", session_id);
+                    return;
+                }
+            }
+        }
+
+        if !chunk.is_empty() {
+            let _ = tx.send(Ok(web::Bytes::from(chunk))).await;
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use actix_web::test as actix_test;
+    use flate2::{write::GzEncoder, Compression};
+    use serde::Serialize;
+
+    use super::*;
+    use crate::test_support::{app_state, authorized_user, database, signed_in_app};
+
+    fn batch() -> serde_json::Value {
+        serde_json::json!({"events": [
+            {
+                "session_id": Uuid::new_v4(),
+                "client_event_id": Uuid::new_v4(),
+                "mouse_action": "left",
+                "mouse_x": 120,
+                "mouse_y": -40,
+                "event_timestamp_nanos": 1_729_245_600_123_456_789_i64,
+            },
+            {
+                "session_id": Uuid::new_v4(),
+                "keyboard_action": {"key": "A", "duration": 85},
+                "mouse_x": 0,
+                "mouse_y": 0,
+                "event_timestamp_nanos": 1_729_245_600_223_456_789_i64,
+            },
+            {
+                "session_id": Uuid::new_v4(),
+                "scroll_action": {"x": 0, "y": -300, "duration": 16},
+                "mouse_x": 800,
+                "mouse_y": 600,
+                "event_timestamp_nanos": 1_729_245_600_323_456_789_i64,
+            },
+        ]})
+    }
+
+    fn msgpack(value: &serde_json::Value) -> Vec<u8> {
+        let mut body = Vec::new();
+        let mut serializer = rmp_serde::Serializer::new(&mut body).with_struct_map().with_human_readable();
+        value.serialize(&mut serializer).unwrap();
+        body
+    }
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    async fn extract(req: actix_test::TestRequest) -> Result<Vec<serde_json::Value>, actix_web::Error> {
+        let (req, mut payload) = req.to_http_parts();
+        DeventBatch::from_request(&req, &mut payload).await.map(|batch| batch.0.events)
+    }
+
+    fn request(content_type: &str, body: Vec<u8>) -> actix_test::TestRequest {
+        actix_test::TestRequest::post()
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .set_payload(body)
+    }
+
+    #[actix_web::test]
+    async fn binary_and_compressed_bodies_decode_like_json() {
+        let batch = batch();
+        let json = serde_json::to_vec(&batch).unwrap();
+        let expected = extract(request("application/json", json.clone())).await.unwrap();
+        assert_eq!(expected, batch["events"].as_array().unwrap().clone());
+
+        let decoded = extract(request("application/msgpack", msgpack(&batch))).await.unwrap();
+        assert_eq!(decoded, expected);
+
+        let decoded = extract(
+            request("application/json", gzip(&json)).insert_header((header::CONTENT_ENCODING, "gzip")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(decoded, expected);
+
+        let decoded = extract(
+            request("application/vnd.msgpack", zstd::encode_all(&msgpack(&batch)[..], 0).unwrap())
+                .insert_header((header::CONTENT_ENCODING, "zstd")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(decoded, expected);
+    }
+
+    #[actix_web::test]
+    async fn decoded_events_parse_into_requests() {
+        let events = extract(request("application/msgpack", msgpack(&batch()))).await.unwrap();
+        for event in &events {
+            DeventRequest::deserialize(event).unwrap();
+        }
+    }
+
+    #[actix_web::test]
+    async fn unsupported_content_type_is_rejected() {
+        let err = extract(request("text/plain", b"{}".to_vec())).await.unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), actix_web::http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[actix_web::test]
+    async fn decompressed_size_is_limited() {
+        let app_config = AppConfig {
+            max_devent_batch_bytes: 1024,
+            ..AppConfig::for_tests("secret")
+        };
+
+        // Compresses to far less than the limit but expands past it
+        let body = gzip(&vec![b' '; 64 * 1024]);
+        let err = extract(
+            request("application/json", body)
+                .insert_header((header::CONTENT_ENCODING, "gzip"))
+                .app_data(web::Data::new(Arc::new(app_config))),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), actix_web::http::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn mixed_batch_stores_valid_events_and_dead_letters_the_rest() {
+        let pool = database().await;
+        let user_id = format!("user_test_{}", Uuid::new_v4());
+        let session = crate::models::Session {
+            user_id: Some(user_id.clone()),
+            ..Default::default()
+        };
+        crate::models::Session::insert(&pool, &session).await.unwrap();
+
+        let app = signed_in_app!(
+            app_state(pool.clone()),
+            authorized_user(&user_id, &[], &[]),
+            web::scope("/devents").service(create_devent)
+        );
+
+        let now = timestamp_nanos(Utc::now());
+        let batch = serde_json::json!({"events": [
+            {"session_id": session.id, "mouse_action": "left", "mouse_x": 1, "mouse_y": 2, "event_timestamp_nanos": now},
+            {"session_id": session.id, "mouse_x": "not a number"},
+            {"session_id": Uuid::new_v4(), "mouse_action": "left", "mouse_x": 1, "mouse_y": 2, "event_timestamp_nanos": now},
+            {"session_id": session.id, "mouse_action": "right", "mouse_x": 3, "mouse_y": 4, "event_timestamp_nanos": now},
+        ]});
+        let req = actix_test::TestRequest::post().uri("/devents/create").set_json(&batch).to_request();
+        let res: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(res["accepted"], 2);
+        let rejected = res["rejected"].as_array().unwrap();
+        let indexes: Vec<u64> = rejected.iter().map(|rejected| rejected["index"].as_u64().unwrap()).collect();
+        assert_eq!(indexes, vec![1, 2]);
+        assert!(rejected.iter().all(|rejected| rejected["reason"].as_str().is_some_and(|reason| !reason.is_empty())));
+
+        let stored: Vec<(i32, serde_json::Value)> = sqlx::query_as(
+            "SELECT batch_index, payload FROM devent_rejections WHERE user_id = $1 ORDER BY batch_index",
+        )
+        .bind(&user_id)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(stored, vec![(1, batch["events"][1].clone()), (2, batch["events"][2].clone())]);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn retried_batches_store_nothing_new() {
+        let pool = database().await;
+        let user_id = format!("user_test_{}", Uuid::new_v4());
+        let sessions = [Uuid::new_v4(), Uuid::new_v4()];
+        for id in sessions {
+            let session = crate::models::Session {
+                id,
+                user_id: Some(user_id.clone()),
+                ..Default::default()
+            };
+            crate::models::Session::insert(&pool, &session).await.unwrap();
+        }
+        let app = signed_in_app!(
+            app_state(pool.clone()),
+            authorized_user(&user_id, &[], &[]),
+            web::scope("/devents").service(create_devent)
+        );
+
+        let now = timestamp_nanos(Utc::now());
+        let client_event_ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let batch = |session_id: Uuid| {
+            let events: Vec<serde_json::Value> = client_event_ids
+                .iter()
+                .map(|client_event_id| {
+                    serde_json::json!({
+                        "session_id": session_id, "client_event_id": client_event_id, "mouse_action": "left",
+                        "mouse_x": 1, "mouse_y": 2, "event_timestamp_nanos": now,
+                    })
+                })
+                .collect();
+            actix_test::TestRequest::post()
+                .uri("/devents/create")
+                .set_json(serde_json::json!({"events": events}))
+                .to_request()
+        };
+        let stored = || async {
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM devents WHERE user_id = $1")
+                .bind(&user_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+        };
+
+        let res: serde_json::Value = actix_test::call_and_read_body_json(&app, batch(sessions[0])).await;
+        assert_eq!((res["accepted"].as_u64(), res["duplicates"].as_u64()), (Some(3), Some(0)));
+        assert_eq!(stored().await, 3);
+
+        let res: serde_json::Value = actix_test::call_and_read_body_json(&app, batch(sessions[0])).await;
+        assert_eq!((res["accepted"].as_u64(), res["duplicates"].as_u64()), (Some(0), Some(3)));
+        assert_eq!(stored().await, 3);
+
+        // Client event ids only have to be unique within a session
+        let res: serde_json::Value = actix_test::call_and_read_body_json(&app, batch(sessions[1])).await;
+        assert_eq!((res["accepted"].as_u64(), res["duplicates"].as_u64()), (Some(3), Some(0)));
+        assert_eq!(stored().await, 6);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn recording_devents_are_the_ones_within_its_time_window() {
+        let pool = database().await;
+        let user_id = format!("user_test_{}", Uuid::new_v4());
+        let session = crate::models::Session {
+            user_id: Some(user_id.clone()),
+            ..Default::default()
+        };
+        crate::models::Session::insert(&pool, &session).await.unwrap();
+
+        // Postgres keeps timestamps to the microsecond
+        let start = timestamp_nanos(Utc::now()) / 1_000 * 1_000;
+        let recording = Recording::new(
+            &pool,
+            Uuid::new_v4(),
+            session.id,
+            user_id.clone(),
+            format!("{}/{}.mp4", session.id, start),
+            start,
+            None,
+            1_000,
+        )
+        .await
+        .unwrap();
+        let at = |event_timestamp_nanos| {
+            Devent::prepare_for_insert(session.id, user_id.clone(), None, None, None, 0, 0, event_timestamp_nanos)
+        };
+        let devents = [
+            at(start - 1),
+            at(start),
+            at(start + 500_000_000),
+            at(start + 1_000_000_000),
+            at(start + 1_000_000_001),
+        ];
+        Devent::batch_insert(&pool, &devents).await.unwrap();
+
+        let owner = signed_in_app!(
+            app_state(pool.clone()),
+            authorized_user(&user_id, &[], &[]),
+            web::scope("/devents").service(get_devents_for_recording)
+        );
+        let page = || {
+            actix_test::TestRequest::get()
+                .uri(&format!("/devents/recording/{}", recording.id))
+                .to_request()
+        };
+        let res: serde_json::Value = actix_test::call_and_read_body_json(&owner, page()).await;
+        let listed: Vec<(String, i64)> = res["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|devent| (devent["id"].as_str().unwrap().to_string(), devent["offset_ms"].as_i64().unwrap()))
+            .collect();
+        let expected: Vec<(String, i64)> = devents[1..4]
+            .iter()
+            .zip([0, 500, 1_000])
+            .map(|(devent, offset_ms)| (devent.id.to_string(), offset_ms))
+            .collect();
+        assert_eq!(listed, expected);
+
+        // A deleted recording no longer has an owner or devents
+        sqlx::query("UPDATE recordings SET deleted_at = CURRENT_TIMESTAMP WHERE id = $1")
+            .bind(recording.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(actix_test::call_service(&owner, page()).await.status(), actix_web::http::StatusCode::FORBIDDEN);
+        let reader = signed_in_app!(
+            app_state(pool.clone()),
+            authorized_user(&format!("user_test_{}", Uuid::new_v4()), &[], &[Permission::DeventsReadAny]),
+            web::scope("/devents").service(get_devents_for_recording)
+        );
+        let res: serde_json::Value = actix_test::call_and_read_body_json(&reader, page()).await;
+        assert_eq!(res["data"], serde_json::json!([]));
+        assert!(Recording::get_upload(&pool, recording.id).await.unwrap().is_none());
+    }
+}
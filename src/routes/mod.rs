@@ -1,4 +1,8 @@
 pub mod hello;
 pub mod devents;
+pub mod devent_stream;
 pub mod recordings;
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod admin;
+pub mod sessions;
+pub mod storage;
\ No newline at end of file
@@ -1,82 +1,769 @@
-use actix_web::{post, web};
+use actix_web::{delete, get, post, web};
 use anyhow::Result;
-use aws_config::{meta::region::RegionProviderChain, Region};
-use aws_sdk_s3::{config::Credentials, presigning::PresigningConfig, Client};
-use std::{sync::Arc, time::Duration};
-use tracing::error;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
 
-use crate::models::Recording;
-use crate::types::SaveRecordingRequest;
-use crate::{config::AppConfig, middleware::auth::AuthenticatedUser, AppState};
+use crate::models::devents::timestamp_nanos;
+use crate::models::recordings::{RecordingStatus, RecordingUpload};
+use crate::models::{ClockSkew, Recording};
+use crate::models::users::Permission;
+use crate::routes::sessions::{get_readable_session, get_writable_session};
+use crate::storage::{ObjectInfo, ObjectStore, UploadedPart};
+use crate::types::{
+    CompleteMultipartUploadRequest, CompleteRecordingRequest, CreateMultipartUploadResponse, SaveRecordingRequest,
+};
+use crate::middleware::auth::{AuthenticatedUser, AuthorizedUser};
+use crate::AppState;
 
-#[post("/fetch_save_url")]
-async fn fetch_save_url(
-    app_state: web::Data<Arc<AppState>>,
-    app_config: web::Data<Arc<AppConfig>>,
-    _authenticated_user: AuthenticatedUser,
-    req_body: web::Json<SaveRecordingRequest>,
-) -> Result<String, actix_web::Error> {
-    let recording_id = req_body.recording_id;
+/// S3 part numbers run from 1 to 10000
+const MAX_PART_NUMBER: i32 = 10000;
+
+/// Every part but the last has to be at least 5 MiB
+const MIN_PART_SIZE_BYTES: i64 = 5 * 1024 * 1024;
+
+fn internal_error(e: anyhow::Error) -> actix_web::Error {
+    error!("Error handling recording upload: {:?}", e);
+    actix_web::error::ErrorInternalServerError(e.to_string())
+}
+
+/// Insert the pending recording row for an upload that is about to start
+async fn create_recording(
+    pool: &PgPool,
+    user_id: &str,
+    req_body: &SaveRecordingRequest,
+) -> Result<Recording, actix_web::Error> {
     let session_id = req_body.session_id;
     let start_timestamp = req_body.start_timestamp_nanos;
-    let duration_ms = req_body.duration_ms;
+    let received_at_nanos = timestamp_nanos(Utc::now());
 
+    get_writable_session(pool, session_id, user_id).await?;
+
+    let duration_ms = i32::try_from(req_body.duration_ms)
+        .map_err(|_| actix_web::error::ErrorBadRequest(format!("duration_ms must be at most {}", i32::MAX)))?;
     let r2_object_key = format!("{}/{}.mp4", session_id, start_timestamp);
 
-    Recording::new(
-        &app_state.pool.clone(),
-        recording_id,
+    let clock_skew = req_body
+        .clock
+        .as_ref()
+        .map(|clock| clock.to_clock_skew(session_id, user_id.to_string(), received_at_nanos));
+    let normalized_start_timestamp = clock_skew
+        .as_ref()
+        .map(|clock_skew| clock_skew.normalize(start_timestamp, req_body.start_monotonic_nanos));
+
+    // The clock sample is only kept along with the recording it was sent for
+    let mut tx = pool.begin().await.map_err(|e| internal_error(e.into()))?;
+    ClockSkew::batch_insert(&mut *tx, clock_skew.as_slice()).await.map_err(internal_error)?;
+    let recording = Recording::new(
+        &mut *tx,
+        req_body.recording_id,
         session_id,
-        r2_object_key.clone(),
+        user_id.to_string(),
+        r2_object_key,
         start_timestamp,
+        normalized_start_timestamp,
         duration_ms,
     )
     .await
-    .map_err(|e| {
-        error!("Error saving recording row: {:?}", e);
-        actix_web::error::ErrorInternalServerError(e.to_string())
-    })?;
+    .map_err(internal_error)?;
+    tx.commit().await.map_err(|e| internal_error(e.into()))?;
 
-    let presigned_url = generate_presigned_url(app_config, r2_object_key.clone())
+    Ok(recording)
+}
+
+/// Create a pending recording and return a presigned URL to PUT it to. Call `/recordings/{id}/complete`
+/// once the upload succeeded, recordings that stay pending are failed by the recording sweeper.
+#[post("/fetch_save_url")]
+async fn fetch_save_url(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    req_body: web::Json<SaveRecordingRequest>,
+) -> Result<String, actix_web::Error> {
+    let recording = create_recording(&app_state.pool, &authenticated_user.user_id, &req_body).await?;
+
+    let presigned_url = app_state.object_store.presign_put(recording.r2_object_key)
         .await
         .map_err(|e| {
             error!("Error getting presigned url: {:?}", e);
             actix_web::error::ErrorInternalServerError(e.to_string())
         })?;
-    
+
+    Ok(presigned_url)
+}
+
+/// Load the upload state of a recording owned by the user, a 404 if it does not exist and a 403 if it
+/// belongs to someone else
+async fn get_own_upload(pool: &PgPool, id: Uuid, user_id: &str) -> Result<RecordingUpload, actix_web::Error> {
+    let upload = Recording::get_upload(pool, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Recording {} not found", id)))?;
+
+    if upload.user_id.as_deref() != Some(user_id) {
+        error!("User {} tried to upload recording {} owned by someone else", user_id, id);
+        return Err(actix_web::error::ErrorForbidden(format!("Recording {} belongs to another user", id)));
+    }
+
+    Ok(upload)
+}
+
+/// Id of the multipart upload in progress for a recording, a 409 if there is none
+fn multipart_upload_id(upload: &RecordingUpload) -> Result<String, actix_web::Error> {
+    match (&upload.upload_id, upload.status) {
+        (Some(upload_id), RecordingStatus::Pending | RecordingStatus::Uploaded) => Ok(upload_id.clone()),
+        _ => Err(actix_web::error::ErrorConflict(format!(
+            "Recording {} has no multipart upload in progress",
+            upload.id
+        ))),
+    }
+}
+
+/// Check an uploaded object against what the client reported, returning why it does not match. The ETag
+/// is only compared if the client sent one, quoted or not.
+fn check_uploaded_object(
+    object: Option<ObjectInfo>,
+    expected_size_bytes: i64,
+    expected_etag: Option<&str>,
+) -> Result<ObjectInfo, String> {
+    let expected_etag = expected_etag.map(|etag| etag.trim_matches('"'));
+    match object {
+        None => Err("object was not found".to_string()),
+        Some(object) if object.size_bytes != expected_size_bytes => Err(format!(
+            "object is {} bytes, expected {}",
+            object.size_bytes, expected_size_bytes
+        )),
+        Some(object) if expected_etag.is_some_and(|etag| object.etag.as_deref() != Some(etag)) => {
+            Err("object ETag does not match".to_string())
+        }
+        Some(object) => Ok(object),
+    }
+}
+
+/// HEAD the object of a recording and mark it verified if it exists with the expected size and ETag, or
+/// failed if not. If storage can't be reached it is left as uploaded so the client can try again.
+async fn verify_upload(
+    pool: &PgPool,
+    object_store: &dyn ObjectStore,
+    upload: RecordingUpload,
+    expected_size_bytes: i64,
+    expected_etag: Option<&str>,
+) -> Result<RecordingUpload, actix_web::Error> {
+    let id = upload.id;
+
+    let object = match object_store.head(upload.r2_object_key.clone()).await {
+        Ok(object) => object,
+        Err(e) => {
+            error!("Error checking recording object {}: {:?}", upload.r2_object_key, e);
+            Recording::mark_uploaded(pool, id).await.map_err(internal_error)?;
+            return Err(actix_web::error::ErrorBadGateway("Could not check the upload, try again later"));
+        }
+    };
+
+    match check_uploaded_object(object, expected_size_bytes, expected_etag) {
+        Ok(object) => {
+            Recording::mark_verified(pool, id, object.size_bytes, object.etag.as_deref())
+                .await
+                .map_err(internal_error)?;
+        }
+        Err(reason) => {
+            info!("Recording {} failed verification: {}", id, reason);
+            Recording::mark_failed(pool, id, &reason).await.map_err(internal_error)?;
+            return Err(actix_web::error::ErrorUnprocessableEntity(format!(
+                "Recording {} could not be verified: {}",
+                id, reason
+            )));
+        }
+    }
+
+    Recording::get_upload(pool, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Recording {} not found", id)))
+}
+
+/// Confirm the client reported upload of a recording against the object in storage. The recording is verified
+/// when the object exists with the reported size, and ETag if one was sent, and failed otherwise. If storage
+/// can't be reached it is left as uploaded and the client can call this again.
+#[post("/{id}/complete")]
+async fn complete_recording(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    req_body: web::Json<CompleteRecordingRequest>,
+) -> Result<web::Json<RecordingUpload>, actix_web::Error> {
+    let id = id.into_inner();
+    let upload = get_own_upload(&app_state.pool, id, &authenticated_user.user_id).await?;
+
+    if upload.status == RecordingStatus::Verified {
+        return Ok(web::Json(upload));
+    }
+    if upload.upload_id.is_some() {
+        return Err(actix_web::error::ErrorConflict(format!(
+            "Recording {} is a multipart upload, complete it with /recordings/{}/multipart/complete",
+            id, id
+        )));
+    }
+
+    let upload = verify_upload(
+        &app_state.pool,
+        app_state.object_store.as_ref(),
+        upload,
+        req_body.size_bytes,
+        req_body.etag.as_deref(),
+    )
+    .await?;
+
+    Ok(web::Json(upload))
+}
+
+/// Create a pending recording that is uploaded in parts. Presign each part with
+/// `/recordings/{id}/multipart/parts/{part_number}/url`, list the parts storage already has to resume after a
+/// network drop, and finish with `/recordings/{id}/multipart/complete`.
+#[post("/multipart")]
+async fn create_multipart_upload(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    req_body: web::Json<SaveRecordingRequest>,
+) -> Result<web::Json<CreateMultipartUploadResponse>, actix_web::Error> {
+    let recording = create_recording(&app_state.pool, &authenticated_user.user_id, &req_body).await?;
+
+    let upload_id = app_state.object_store.create_multipart_upload(recording.r2_object_key.clone())
+        .await
+        .map_err(|e| {
+            error!("Error creating multipart upload: {:?}", e);
+            actix_web::error::ErrorBadGateway(e.to_string())
+        })?;
+    Recording::start_multipart(&app_state.pool, recording.id, &upload_id)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(web::Json(CreateMultipartUploadResponse {
+        recording_id: recording.id,
+        max_part_number: MAX_PART_NUMBER,
+        min_part_size_bytes: MIN_PART_SIZE_BYTES,
+    }))
+}
+
+/// Presigned URL to PUT one part to, the response `ETag` header of the PUT identifies the part
+#[get("/{id}/multipart/parts/{part_number}/url")]
+async fn get_upload_part_url(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    path: web::Path<(Uuid, i32)>,
+) -> Result<String, actix_web::Error> {
+    let (id, part_number) = path.into_inner();
+    if !(1..=MAX_PART_NUMBER).contains(&part_number) {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "part_number must be between 1 and {}",
+            MAX_PART_NUMBER
+        )));
+    }
+
+    let upload = get_own_upload(&app_state.pool, id, &authenticated_user.user_id).await?;
+    let upload_id = multipart_upload_id(&upload)?;
+
+    let presigned_url = app_state.object_store.presign_upload_part(upload.r2_object_key, upload_id, part_number)
+        .await
+        .map_err(|e| {
+            error!("Error getting presigned part url: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e.to_string())
+        })?;
+    Recording::touch(&app_state.pool, id).await.map_err(internal_error)?;
+
+    Ok(presigned_url)
+}
+
+/// Parts storage already has, in part number order. A client resuming an upload only sends the missing ones.
+#[get("/{id}/multipart/parts")]
+async fn list_upload_parts(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+) -> Result<web::Json<Vec<UploadedPart>>, actix_web::Error> {
+    let upload = get_own_upload(&app_state.pool, id.into_inner(), &authenticated_user.user_id).await?;
+    let upload_id = multipart_upload_id(&upload)?;
+
+    let parts = app_state.object_store.list_parts(upload.r2_object_key, upload_id)
+        .await
+        .map_err(|e| {
+            error!("Error listing upload parts: {:?}", e);
+            actix_web::error::ErrorBadGateway(e.to_string())
+        })?;
+
+    Ok(web::Json(parts))
+}
+
+/// Part numbers below `part_count` that are smaller than storage allows for any part but the last
+fn undersized_parts(parts: &[UploadedPart], part_count: i32) -> Vec<i32> {
+    parts
+        .iter()
+        .filter(|part| part.part_number < part_count && part.size_bytes < MIN_PART_SIZE_BYTES)
+        .map(|part| part.part_number)
+        .collect()
+}
+
+/// Assemble the recording from parts 1 to `part_count` and verify it. Fails with a 409 listing the
+/// missing part numbers if storage does not have all of them yet, or the parts other than the last that
+/// are too small. If storage can't assemble it the recording stays pending and this can be called again.
+/// If the assembled object can't be checked the recording is left as uploaded, and calling this again
+/// only checks it.
+#[post("/{id}/multipart/complete")]
+async fn complete_multipart_upload(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    req_body: web::Json<CompleteMultipartUploadRequest>,
+) -> Result<web::Json<RecordingUpload>, actix_web::Error> {
+    let id = id.into_inner();
+    let part_count = req_body.part_count;
+    if !(1..=MAX_PART_NUMBER).contains(&part_count) {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "part_count must be between 1 and {}",
+            MAX_PART_NUMBER
+        )));
+    }
+
+    let upload = get_own_upload(&app_state.pool, id, &authenticated_user.user_id).await?;
+    if upload.status == RecordingStatus::Verified {
+        return Ok(web::Json(upload));
+    }
+    if let (RecordingStatus::Uploaded, None, Some(size_bytes)) = (upload.status, &upload.upload_id, upload.size_bytes) {
+        let upload = verify_upload(&app_state.pool, app_state.object_store.as_ref(), upload, size_bytes, None).await?;
+        return Ok(web::Json(upload));
+    }
+    let upload_id = multipart_upload_id(&upload)?;
+
+    let parts = app_state.object_store.list_parts(upload.r2_object_key.clone(), upload_id.clone())
+        .await
+        .map_err(|e| {
+            error!("Error listing upload parts: {:?}", e);
+            actix_web::error::ErrorBadGateway(e.to_string())
+        })?;
+    let parts: Vec<UploadedPart> = parts.into_iter().filter(|part| part.part_number <= part_count).collect();
+
+    let missing: Vec<String> = (1..=part_count)
+        .filter(|part_number| !parts.iter().any(|part| part.part_number == *part_number))
+        .map(|part_number| part_number.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(actix_web::error::ErrorConflict(format!(
+            "Recording {} is missing parts {}",
+            id,
+            missing.join(", ")
+        )));
+    }
+
+    // Storage would refuse these, and retrying would not change that
+    let undersized = undersized_parts(&parts, part_count);
+    if !undersized.is_empty() {
+        return Err(actix_web::error::ErrorConflict(format!(
+            "Recording {} has parts smaller than {} bytes before its last part: {}",
+            id,
+            MIN_PART_SIZE_BYTES,
+            undersized.iter().map(|part_number| part_number.to_string()).collect::<Vec<_>>().join(", ")
+        )));
+    }
+
+    let completed_parts: Vec<(i32, String)> = parts.iter().map(|part| (part.part_number, part.etag.clone())).collect();
+    if let Err(e) = app_state
+        .object_store
+        .complete_multipart_upload(upload.r2_object_key.clone(), upload_id, completed_parts)
+        .await
+    {
+        // Still pending, the parts are all there and completing can be tried again
+        error!("Error completing multipart upload of recording {}: {:?}", id, e);
+        return Err(actix_web::error::ErrorBadGateway("Could not complete the upload, try again later"));
+    }
+    let size_bytes = parts.iter().map(|part| part.size_bytes).sum();
+    Recording::finish_multipart(&app_state.pool, id, size_bytes).await.map_err(internal_error)?;
+
+    let upload = verify_upload(&app_state.pool, app_state.object_store.as_ref(), upload, size_bytes, None).await?;
+
+    Ok(web::Json(upload))
+}
+
+/// Abort a multipart upload, dropping the parts uploaded so far and failing the recording
+#[delete("/{id}/multipart")]
+async fn abort_multipart_upload(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+) -> Result<web::Json<RecordingUpload>, actix_web::Error> {
+    let id = id.into_inner();
+    let pool = &app_state.pool;
+    let upload = get_own_upload(pool, id, &authenticated_user.user_id).await?;
+    let upload_id = multipart_upload_id(&upload)?;
+
+    // Fail it first, if the abort does not go through the sweeper retries it
+    Recording::mark_failed(pool, id, "upload was aborted").await.map_err(internal_error)?;
+    app_state.object_store.abort_multipart_upload(upload.r2_object_key, upload_id)
+        .await
+        .map_err(|e| {
+            error!("Error aborting multipart upload of recording {}: {:?}", id, e);
+            actix_web::error::ErrorBadGateway(e.to_string())
+        })?;
+    Recording::clear_upload_id(pool, id).await.map_err(internal_error)?;
+
+    let upload = Recording::get_upload(pool, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Recording {} not found", id)))?;
+
+    Ok(web::Json(upload))
+}
+
+/// Load a recording the user owns, or any recording if they have `permission`. Fails with a 404 if it
+/// does not exist and a 403 if it belongs to someone else.
+async fn get_readable_recording(
+    pool: &PgPool,
+    id: Uuid,
+    authorized_user: &AuthorizedUser,
+    permission: Permission,
+) -> Result<Recording, actix_web::Error> {
+    let recording = Recording::get(pool, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Recording {} not found", id)))?;
+
+    if recording.user_id.as_ref() != Some(&authorized_user.user_id) {
+        authorized_user.require(permission)?;
+    }
+
+    Ok(recording)
+}
+
+/// Every recording of a session, in the order they were started
+#[get("/session/{session_id}")]
+async fn get_recordings_for_session(
+    app_state: web::Data<Arc<AppState>>,
+    authorized_user: AuthorizedUser,
+    session_id: web::Path<Uuid>,
+) -> Result<web::Json<Vec<Recording>>, actix_web::Error> {
+    let session_id = session_id.into_inner();
+    get_readable_session(&app_state.pool, session_id, &authorized_user, Permission::RecordingsReadAny).await?;
+
+    let recordings = Recording::get_all_for_session(&app_state.pool, session_id)
+        .await
+        .map_err(|e| {
+            error!("Error getting recordings: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(recordings))
+}
+
+#[get("/{id}")]
+async fn get_recording(
+    app_state: web::Data<Arc<AppState>>,
+    authorized_user: AuthorizedUser,
+    id: web::Path<Uuid>,
+) -> Result<web::Json<Recording>, actix_web::Error> {
+    let recording = get_readable_recording(
+        &app_state.pool,
+        id.into_inner(),
+        &authorized_user,
+        Permission::RecordingsReadAny,
+    )
+    .await?;
+
+    Ok(web::Json(recording))
+}
+
+/// Presigned URL to GET the video of a recording from. Fails with a 409 until the upload has been verified
+/// against storage.
+#[get("/{id}/download")]
+async fn get_recording_download_url(
+    app_state: web::Data<Arc<AppState>>,
+    authorized_user: AuthorizedUser,
+    id: web::Path<Uuid>,
+) -> Result<String, actix_web::Error> {
+    let recording = get_readable_recording(
+        &app_state.pool,
+        id.into_inner(),
+        &authorized_user,
+        Permission::RecordingsDownload,
+    )
+    .await?;
+
+    if recording.status != RecordingStatus::Verified {
+        return Err(actix_web::error::ErrorConflict(format!(
+            "Recording {} has not been verified",
+            recording.id
+        )));
+    }
+
+    let presigned_url = app_state.object_store.presign_get(recording.r2_object_key)
+        .await
+        .map_err(|e| {
+            error!("Error getting presigned download url: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e.to_string())
+        })?;
+
+    info!("User {} downloaded recording {}", authorized_user.user_id, recording.id);
     Ok(presigned_url)
 }
 
-async fn generate_presigned_url(
-    app_config: web::Data<Arc<AppConfig>>,
-    object_key: String,
-) -> Result<String> {
-    let region_provider = RegionProviderChain::default_provider().or_else(Region::new("auto"));
-    let credentials = Credentials::new(
-        app_config.r2_access_key_id.clone(),
-        app_config.r2_secret_access_key.clone(),
-        None,
-        None,
-        "env-credentials",
-    );
-
-    let config = aws_config::from_env()
-        .region(region_provider)
-        .credentials_provider(credentials)
-        .endpoint_url(app_config.r2_endpoint_url.clone())
-        .load()
-        .await;
-
-    let client = Client::new(&config);
-
-    let bucket_name = "ghost-videos";
-
-    let presigned_request = client
-        .put_object()
-        .bucket(bucket_name)
-        .key(object_key)
-        .presigned(PresigningConfig::expires_in(Duration::from_secs(3000))?)
-        .await?;
-
-    Ok(presigned_request.uri().to_string())
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self as actix_test, TestRequest};
+    use futures::future::BoxFuture;
+
+    use super::*;
+    use crate::models::Session;
+    use crate::storage::ObjectSummary;
+    use crate::test_support::{app_state, app_state_with_store, authorized_user, database, signed_in_app};
+
+    /// The recording read routes
+    fn routes() -> actix_web::Scope {
+        web::scope("/recordings")
+            .service(get_recordings_for_session)
+            .service(get_recording_download_url)
+            .service(get_recording)
+    }
+
+    async fn recording(pool: &PgPool, session: &Session, start_timestamp_nanos: i64) -> Recording {
+        Recording::new(
+            pool,
+            Uuid::new_v4(),
+            session.id,
+            session.user_id.clone().unwrap(),
+            format!("{}/{}.mp4", session.id, start_timestamp_nanos),
+            start_timestamp_nanos,
+            None,
+            1000,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn recordings_are_readable_by_their_owner_and_with_permissions() {
+        let pool = database().await;
+        let owner_id = format!("user_test_{}", Uuid::new_v4());
+        let session = Session {
+            user_id: Some(owner_id.clone()),
+            ..Default::default()
+        };
+        Session::insert(&pool, &session).await.unwrap();
+        let verified = recording(&pool, &session, 1_000).await;
+        Recording::mark_verified(&pool, verified.id, 10, Some("etag")).await.unwrap();
+        let pending = recording(&pool, &session, 2_000).await;
+
+        let someone = |permissions| authorized_user(&format!("user_test_{}", Uuid::new_v4()), &[], permissions);
+        let owner = signed_in_app!(app_state(pool.clone()), authorized_user(&owner_id, &[], &[]), routes());
+        let stranger = signed_in_app!(app_state(pool.clone()), someone(&[]), routes());
+        let reader = signed_in_app!(app_state(pool.clone()), someone(&[Permission::RecordingsReadAny]), routes());
+        let downloader = signed_in_app!(
+            app_state(pool.clone()),
+            someone(&[Permission::RecordingsReadAny, Permission::RecordingsDownload]),
+            routes()
+        );
+
+        let list = || TestRequest::get().uri(&format!("/recordings/session/{}", session.id)).to_request();
+        let get = || TestRequest::get().uri(&format!("/recordings/{}", verified.id)).to_request();
+        let download = |id: Uuid| TestRequest::get().uri(&format!("/recordings/{}/download", id)).to_request();
+
+        let ids = vec![verified.id, pending.id];
+        let recordings: Vec<Recording> = actix_test::call_and_read_body_json(&owner, list()).await;
+        assert_eq!(recordings.iter().map(|recording| recording.id).collect::<Vec<_>>(), ids);
+        let recordings: Vec<Recording> = actix_test::call_and_read_body_json(&reader, list()).await;
+        assert_eq!(recordings.iter().map(|recording| recording.id).collect::<Vec<_>>(), ids);
+        assert_eq!(actix_test::call_service(&stranger, list()).await.status(), StatusCode::FORBIDDEN);
+
+        let recording: Recording = actix_test::call_and_read_body_json(&owner, get()).await;
+        assert_eq!(recording.status, RecordingStatus::Verified);
+        let recording: Recording = actix_test::call_and_read_body_json(&reader, get()).await;
+        assert_eq!(recording.id, verified.id);
+        assert_eq!(actix_test::call_service(&stranger, get()).await.status(), StatusCode::FORBIDDEN);
+
+        // Reading any recording does not allow downloading it
+        assert_eq!(actix_test::call_service(&owner, download(verified.id)).await.status(), StatusCode::OK);
+        assert_eq!(actix_test::call_service(&downloader, download(verified.id)).await.status(), StatusCode::OK);
+        assert_eq!(actix_test::call_service(&reader, download(verified.id)).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(actix_test::call_service(&stranger, download(verified.id)).await.status(), StatusCode::FORBIDDEN);
+
+        // Only verified uploads can be downloaded
+        let res = actix_test::call_service(&owner, download(pending.id)).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let res = actix_test::call_service(&owner, download(Uuid::new_v4())).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// Storage holding a one part multipart upload of `SIZE_BYTES`, whose `head` fails until it is reachable
+    struct FlakyStore {
+        reachable: AtomicBool,
+    }
+
+    impl FlakyStore {
+        const SIZE_BYTES: i64 = 11;
+    }
+
+    impl ObjectStore for FlakyStore {
+        fn presign_put(&self, object_key: String) -> BoxFuture<'_, anyhow::Result<String>> {
+            Box::pin(async move { Ok(format!("http://storage/{}", object_key)) })
+        }
+
+        fn presign_get(&self, object_key: String) -> BoxFuture<'_, anyhow::Result<String>> {
+            Box::pin(async move { Ok(format!("http://storage/{}", object_key)) })
+        }
+
+        fn head(&self, _object_key: String) -> BoxFuture<'_, anyhow::Result<Option<ObjectInfo>>> {
+            Box::pin(async move {
+                if !self.reachable.load(Ordering::SeqCst) {
+                    return Err(anyhow::anyhow!("storage is unreachable"));
+                }
+                Ok(object(Self::SIZE_BYTES, Some("etag")))
+            })
+        }
+
+        fn delete(&self, _object_key: String) -> BoxFuture<'_, anyhow::Result<()>> {
+            Box::pin(async move { Ok(()) })
+        }
+
+        fn list(&self, _prefix: String) -> BoxFuture<'_, anyhow::Result<Vec<ObjectSummary>>> {
+            Box::pin(async move { Ok(Vec::new()) })
+        }
+
+        fn create_multipart_upload(&self, _object_key: String) -> BoxFuture<'_, anyhow::Result<String>> {
+            Box::pin(async move { Ok("upload".to_string()) })
+        }
+
+        fn presign_upload_part(
+            &self,
+            object_key: String,
+            _upload_id: String,
+            part_number: i32,
+        ) -> BoxFuture<'_, anyhow::Result<String>> {
+            Box::pin(async move { Ok(format!("http://storage/{}/{}", object_key, part_number)) })
+        }
+
+        fn list_parts(
+            &self,
+            _object_key: String,
+            _upload_id: String,
+        ) -> BoxFuture<'_, anyhow::Result<Vec<UploadedPart>>> {
+            Box::pin(async move {
+                Ok(vec![UploadedPart {
+                    part_number: 1,
+                    etag: "etag-1".to_string(),
+                    size_bytes: Self::SIZE_BYTES,
+                    last_modified: None,
+                }])
+            })
+        }
+
+        fn complete_multipart_upload(
+            &self,
+            _object_key: String,
+            _upload_id: String,
+            _parts: Vec<(i32, String)>,
+        ) -> BoxFuture<'_, anyhow::Result<()>> {
+            Box::pin(async move { Ok(()) })
+        }
+
+        fn abort_multipart_upload(&self, _object_key: String, _upload_id: String) -> BoxFuture<'_, anyhow::Result<()>> {
+            Box::pin(async move { Ok(()) })
+        }
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn completing_a_multipart_upload_again_checks_the_assembled_object() {
+        let pool = database().await;
+        let user_id = format!("user_test_{}", Uuid::new_v4());
+        let session = Session {
+            user_id: Some(user_id.clone()),
+            ..Default::default()
+        };
+        Session::insert(&pool, &session).await.unwrap();
+
+        let store = Arc::new(FlakyStore {
+            reachable: AtomicBool::new(false),
+        });
+        let app = signed_in_app!(
+            app_state_with_store(pool.clone(), store.clone()),
+            authorized_user(&user_id, &[], &[]),
+            web::scope("/recordings").service(create_multipart_upload).service(complete_multipart_upload)
+        );
+
+        let recording_id = Uuid::new_v4();
+        let req = TestRequest::post()
+            .uri("/recordings/multipart")
+            .set_json(serde_json::json!({
+                "recording_id": recording_id,
+                "session_id": session.id,
+                "start_timestamp_nanos": 1_000,
+                "duration_ms": 1000,
+            }))
+            .to_request();
+        assert_eq!(actix_test::call_service(&app, req).await.status(), StatusCode::OK);
+        let complete = || {
+            TestRequest::post()
+                .uri(&format!("/recordings/{}/multipart/complete", recording_id))
+                .set_json(serde_json::json!({"part_count": 1}))
+                .to_request()
+        };
+
+        // Assembled, but the object could not be checked
+        assert_eq!(actix_test::call_service(&app, complete()).await.status(), StatusCode::BAD_GATEWAY);
+        let upload = Recording::get_upload(&pool, recording_id).await.unwrap().unwrap();
+        assert_eq!(upload.status, RecordingStatus::Uploaded);
+        assert_eq!(upload.upload_id, None);
+        assert_eq!(upload.size_bytes, Some(FlakyStore::SIZE_BYTES));
+
+        store.reachable.store(true, Ordering::SeqCst);
+        let upload: serde_json::Value = actix_test::call_and_read_body_json(&app, complete()).await;
+        assert_eq!(upload["status"], "verified");
+        assert_eq!(upload["size_bytes"], FlakyStore::SIZE_BYTES);
+    }
+
+    fn object(size_bytes: i64, etag: Option<&str>) -> Option<ObjectInfo> {
+        Some(ObjectInfo {
+            size_bytes,
+            etag: etag.map(|etag| etag.to_string()),
+        })
+    }
+
+    #[test]
+    fn only_the_last_part_may_be_small() {
+        let part = |part_number, size_bytes| UploadedPart {
+            part_number,
+            etag: format!("etag-{}", part_number),
+            size_bytes,
+            last_modified: None,
+        };
+
+        let parts = [part(1, MIN_PART_SIZE_BYTES), part(2, MIN_PART_SIZE_BYTES + 1), part(3, 1)];
+        assert!(undersized_parts(&parts, 3).is_empty());
+
+        let parts = [part(1, MIN_PART_SIZE_BYTES - 1), part(2, 1), part(3, 1)];
+        assert_eq!(undersized_parts(&parts, 3), vec![1, 2]);
+    }
+
+    #[test]
+    fn uploaded_objects_are_checked_against_the_reported_size_and_etag() {
+        assert_eq!(check_uploaded_object(None, 10, None).unwrap_err(), "object was not found");
+        assert_eq!(
+            check_uploaded_object(object(9, Some("abc")), 10, None).unwrap_err(),
+            "object is 9 bytes, expected 10"
+        );
+        assert_eq!(
+            check_uploaded_object(object(10, Some("abc")), 10, Some("def")).unwrap_err(),
+            "object ETag does not match"
+        );
+        assert_eq!(
+            check_uploaded_object(object(10, None), 10, Some("abc")).unwrap_err(),
+            "object ETag does not match"
+        );
+
+        let verified = check_uploaded_object(object(10, Some("abc")), 10, Some("\"abc\"")).unwrap();
+        assert_eq!(verified.etag.as_deref(), Some("abc"));
+        assert!(check_uploaded_object(object(10, Some("abc")), 10, None).is_ok());
+    }
 }
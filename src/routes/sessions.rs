@@ -0,0 +1,398 @@
+use actix_web::{get, post, web};
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::middleware::auth::{AuthenticatedUser, AuthorizedUser};
+use crate::models::users::Permission;
+use crate::models::{ClockSkew, Session, SessionDisplay};
+use crate::types::{CreateSessionRequest, EndSessionRequest, UpdateDisplaysRequest};
+use crate::validation::sessions::validate_displays;
+use crate::AppState;
+
+/// Load a session the user is allowed to write devents and recordings into. Fails with a 404 if it
+/// does not exist, a 403 if it belongs to someone else and a 409 if it has already ended.
+pub async fn get_writable_session(
+    pool: &PgPool,
+    session_id: Uuid,
+    user_id: &str,
+) -> Result<Session, actix_web::Error> {
+    let session = get_session_or_404(pool, session_id).await?;
+
+    if session.user_id.as_deref() != Some(user_id) {
+        error!("User {} tried to write to session {} owned by someone else", user_id, session_id);
+        return Err(actix_web::error::ErrorForbidden(format!(
+            "Session {} belongs to another user",
+            session_id
+        )));
+    }
+
+    if !session.is_open() {
+        return Err(actix_web::error::ErrorConflict(format!(
+            "Session {} has already ended",
+            session_id
+        )));
+    }
+
+    Ok(session)
+}
+
+/// Load a session the user owns, or any session if they may read everyone's
+pub async fn get_readable_session(
+    pool: &PgPool,
+    session_id: Uuid,
+    authorized_user: &AuthorizedUser,
+    permission: Permission,
+) -> Result<Session, actix_web::Error> {
+    let session = get_session_or_404(pool, session_id).await?;
+
+    if session.user_id.as_ref() != Some(&authorized_user.user_id) {
+        authorized_user.require(permission)?;
+    }
+
+    Ok(session)
+}
+
+async fn get_session_or_404(pool: &PgPool, session_id: Uuid) -> Result<Session, actix_web::Error> {
+    Session::get(pool, session_id)
+        .await
+        .map_err(|e| {
+            error!("Error getting session: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Session {} not found", session_id)))
+}
+
+#[post("/start")]
+async fn start_session(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    req_body: web::Json<CreateSessionRequest>,
+) -> Result<web::Json<Session>, actix_web::Error> {
+    let req_body = req_body.into_inner();
+    validate_displays(&req_body.displays).map_err(actix_web::error::ErrorBadRequest)?;
+
+    let started_at = req_body
+        .start_timestamp_nanos
+        .map(|nanos| Utc.timestamp_nanos(nanos))
+        .unwrap_or_else(Utc::now);
+
+    let session = Session {
+        id: req_body.session_id.unwrap_or_else(Uuid::new_v4),
+        user_id: Some(authenticated_user.user_id.clone()),
+        app_version: req_body.app_version,
+        os: req_body.os,
+        device_id: req_body.device_id,
+        screen_width: req_body.screen_width,
+        screen_height: req_body.screen_height,
+        started_at,
+        ..Default::default()
+    };
+
+    let internal_error = |e: anyhow::Error| {
+        error!("Error creating session: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    };
+    let mut tx = app_state.pool.begin().await.map_err(|e| internal_error(e.into()))?;
+    let inserted = Session::insert(&mut *tx, &session).await.map_err(internal_error)?;
+
+    // Starting is idempotent so the client can safely retry it, also while the first attempt is in flight.
+    // A retry sends the displays again, they are saved either way.
+    let (session, started) = match inserted {
+        Some(session) => (session, true),
+        None => {
+            let existing = get_session_or_404(&app_state.pool, session.id).await?;
+            if existing.user_id.as_ref() != Some(&authenticated_user.user_id) {
+                return Err(actix_web::error::ErrorForbidden(format!(
+                    "Session {} belongs to another user",
+                    session.id
+                )));
+            }
+            (existing, false)
+        }
+    };
+
+    let displays: Vec<SessionDisplay> = req_body
+        .displays
+        .iter()
+        .map(|display| display.to_session_display(session.id))
+        .collect();
+    SessionDisplay::upsert_all(&mut *tx, &displays).await.map_err(internal_error)?;
+    tx.commit().await.map_err(|e| internal_error(e.into()))?;
+
+    if started {
+        info!("User {} started session {}", authenticated_user.user_id, session.id);
+    }
+    Ok(web::Json(session))
+}
+
+#[post("/{id}/heartbeat")]
+async fn heartbeat_session(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+) -> Result<web::Json<Session>, actix_web::Error> {
+    let session = get_writable_session(&app_state.pool, id.into_inner(), &authenticated_user.user_id).await?;
+
+    let session = Session::heartbeat(&app_state.pool, session.id)
+        .await
+        .map_err(|e| {
+            error!("Error updating session heartbeat: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| actix_web::error::ErrorConflict(format!("Session {} has already ended", session.id)))?;
+
+    Ok(web::Json(session))
+}
+
+#[post("/{id}/end")]
+async fn end_session(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    req_body: Option<web::Json<EndSessionRequest>>,
+) -> Result<web::Json<Session>, actix_web::Error> {
+    let session = get_writable_session(&app_state.pool, id.into_inner(), &authenticated_user.user_id).await?;
+
+    let ended_at = req_body
+        .and_then(|req_body| req_body.end_timestamp_nanos)
+        .map(|nanos| Utc.timestamp_nanos(nanos))
+        .unwrap_or_else(Utc::now);
+
+    let session = Session::end(&app_state.pool, session.id, ended_at)
+        .await
+        .map_err(|e| {
+            error!("Error ending session: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| actix_web::error::ErrorConflict(format!("Session {} has already ended", session.id)))?;
+
+    info!("User {} ended session {}", authenticated_user.user_id, session.id);
+    Ok(web::Json(session))
+}
+
+#[get("")]
+async fn get_my_sessions(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+) -> Result<web::Json<Vec<Session>>, actix_web::Error> {
+    let sessions = Session::get_all_for_user(&app_state.pool, &authenticated_user.user_id)
+        .await
+        .map_err(|e| {
+            error!("Error getting sessions: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(sessions))
+}
+
+#[get("/{id}")]
+async fn get_session(
+    app_state: web::Data<Arc<AppState>>,
+    authorized_user: AuthorizedUser,
+    id: web::Path<Uuid>,
+) -> Result<web::Json<Session>, actix_web::Error> {
+    let session = get_readable_session(
+        &app_state.pool,
+        id.into_inner(),
+        &authorized_user,
+        Permission::SessionsReadAny,
+    )
+    .await?;
+
+    Ok(web::Json(session))
+}
+
+/// Report the displays attached to a session, sent again whenever they change during the session
+#[post("/{id}/displays")]
+async fn update_session_displays(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    req_body: web::Json<UpdateDisplaysRequest>,
+) -> Result<web::Json<Vec<SessionDisplay>>, actix_web::Error> {
+    let session = get_writable_session(&app_state.pool, id.into_inner(), &authenticated_user.user_id).await?;
+    validate_displays(&req_body.displays).map_err(actix_web::error::ErrorBadRequest)?;
+
+    let displays: Vec<SessionDisplay> = req_body
+        .displays
+        .iter()
+        .map(|display| display.to_session_display(session.id))
+        .collect();
+    SessionDisplay::upsert_all(&app_state.pool, &displays).await.map_err(|e| {
+        error!("Error saving session displays: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    get_displays(&app_state.pool, session.id).await
+}
+
+#[get("/{id}/displays")]
+async fn get_session_displays(
+    app_state: web::Data<Arc<AppState>>,
+    authorized_user: AuthorizedUser,
+    id: web::Path<Uuid>,
+) -> Result<web::Json<Vec<SessionDisplay>>, actix_web::Error> {
+    let session = get_readable_session(
+        &app_state.pool,
+        id.into_inner(),
+        &authorized_user,
+        Permission::SessionsReadAny,
+    )
+    .await?;
+
+    get_displays(&app_state.pool, session.id).await
+}
+
+async fn get_displays(pool: &PgPool, session_id: Uuid) -> Result<web::Json<Vec<SessionDisplay>>, actix_web::Error> {
+    let displays = SessionDisplay::get_all_for_sessions(pool, &[session_id])
+        .await
+        .map_err(|e| {
+            error!("Error getting session displays: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(displays))
+}
+
+/// Offsets measured between the client clock and the server clock over a session, oldest first
+#[get("/{id}/clock_skews")]
+async fn get_session_clock_skews(
+    app_state: web::Data<Arc<AppState>>,
+    authorized_user: AuthorizedUser,
+    id: web::Path<Uuid>,
+) -> Result<web::Json<Vec<ClockSkew>>, actix_web::Error> {
+    let session = get_readable_session(
+        &app_state.pool,
+        id.into_inner(),
+        &authorized_user,
+        Permission::SessionsReadAny,
+    )
+    .await?;
+
+    let clock_skews = ClockSkew::get_all_for_session(&app_state.pool, session.id)
+        .await
+        .map_err(|e| {
+            error!("Error getting clock skews: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(clock_skews))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self as actix_test, TestRequest};
+
+    use super::*;
+    use crate::test_support::{app_state, authorized_user, database, signed_in_app};
+
+    /// The session routes
+    fn routes() -> actix_web::Scope {
+        web::scope("/sessions")
+            .service(start_session)
+            .service(heartbeat_session)
+            .service(end_session)
+            .service(get_my_sessions)
+            .service(get_session)
+    }
+
+    fn start(session_id: Uuid) -> TestRequest {
+        TestRequest::post()
+            .uri("/sessions/start")
+            .set_json(serde_json::json!({"session_id": session_id, "os": "macos"}))
+    }
+
+    fn post(path: String) -> TestRequest {
+        TestRequest::post().uri(&format!("/sessions{}", path))
+    }
+
+    fn get(path: String) -> TestRequest {
+        TestRequest::get().uri(&format!("/sessions{}", path))
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn session_lifecycle() {
+        let pool = database().await;
+        let user_id = format!("user_test_{}", Uuid::new_v4());
+        let app = signed_in_app!(app_state(pool.clone()), authorized_user(&user_id, &[], &[]), routes());
+        let other_id = format!("user_test_{}", Uuid::new_v4());
+        let other = signed_in_app!(app_state(pool.clone()), authorized_user(&other_id, &[], &[]), routes());
+        let session_id = Uuid::new_v4();
+
+        let started: Session = actix_test::call_and_read_body_json(&app, start(session_id).to_request()).await;
+        assert_eq!(started.id, session_id);
+        assert_eq!(started.user_id.as_deref(), Some(user_id.as_str()));
+        assert!(started.is_open());
+
+        // Retrying returns the same session, starting someone else's is refused
+        let retried: Session = actix_test::call_and_read_body_json(&app, start(session_id).to_request()).await;
+        assert_eq!(retried.started_at, started.started_at);
+
+        // Displays sent with a retry are saved too
+        let req = TestRequest::post()
+            .uri("/sessions/start")
+            .set_json(serde_json::json!({
+                "session_id": session_id,
+                "displays": [{"display_id": "1", "is_primary": true, "x": 0, "y": 0, "width": 1440, "height": 900, "scale_factor": 2.0}],
+            }))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let displays = SessionDisplay::get_all_for_sessions(&pool, &[session_id]).await.unwrap();
+        assert_eq!(displays.iter().map(|display| display.display_id.as_str()).collect::<Vec<_>>(), vec!["1"]);
+        let res = actix_test::call_service(&other, start(session_id).to_request()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = actix_test::call_service(&app, post(format!("/{}/heartbeat", session_id)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = actix_test::call_service(&other, post(format!("/{}/heartbeat", session_id)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let fetched: Session =
+            actix_test::call_and_read_body_json(&app, get(format!("/{}", session_id)).to_request()).await;
+        assert_eq!(fetched.id, session_id);
+        let res = actix_test::call_service(&other, get(format!("/{}", session_id)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = actix_test::call_service(&app, get(format!("/{}", Uuid::new_v4())).to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let mine: Vec<Session> = actix_test::call_and_read_body_json(&app, get(String::new()).to_request()).await;
+        assert_eq!(
+            mine.iter().map(|session| session.id).collect::<Vec<_>>(),
+            vec![session_id]
+        );
+        let theirs: Vec<Session> = actix_test::call_and_read_body_json(&other, get(String::new()).to_request()).await;
+        assert!(theirs.is_empty());
+
+        let ended: Session =
+            actix_test::call_and_read_body_json(&app, post(format!("/{}/end", session_id)).to_request()).await;
+        assert!(!ended.is_open());
+        let res = actix_test::call_service(&app, post(format!("/{}/end", session_id)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let res = actix_test::call_service(&app, post(format!("/{}/heartbeat", session_id)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn concurrent_starts_of_one_session_both_succeed() {
+        let pool = database().await;
+        let user_id = format!("user_test_{}", Uuid::new_v4());
+        let app = signed_in_app!(app_state(pool.clone()), authorized_user(&user_id, &[], &[]), routes());
+        let session_id = Uuid::new_v4();
+
+        let (first, second) = futures::join!(
+            actix_test::call_service(&app, start(session_id).to_request()),
+            actix_test::call_service(&app, start(session_id).to_request()),
+        );
+
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(second.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,181 @@
+use actix_web::body::SizedStream;
+use actix_web::http::header::{self, ContentRange, ContentRangeSpec, Range};
+use actix_web::{get, put, web, HttpRequest, HttpResponse};
+use std::io::SeekFrom;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tracing::{error, warn};
+
+use crate::storage::local::{GrantMethod, LocalStore, MAX_PUT_SIZE_BYTES};
+use crate::types::SignedUrlQuery;
+
+/// Only registered with the local storage backend, stands in for the presigned R2 URLs. The `token` of
+/// the URL is checked instead of a bearer token. Like R2 it needs a `Content-Length` of at most
+/// `MAX_PUT_SIZE_BYTES`.
+#[put("/{key:.*}")]
+async fn put_object(
+    req: HttpRequest,
+    local_store: web::Data<Arc<LocalStore>>,
+    key: web::Path<String>,
+    query: web::Query<SignedUrlQuery>,
+    body: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    let key = key.into_inner();
+    let grant = local_store.verify(&query.token, &key, GrantMethod::Put).map_err(|e| {
+        warn!("Rejected upload of {}: {:?}", key, e);
+        actix_web::error::ErrorForbidden("Invalid or expired upload URL")
+    })?;
+
+    // The payload ends after `Content-Length` bytes, so checking the header bounds the upload
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok())
+        .ok_or_else(|| actix_web::error::ErrorLengthRequired("Content-Length is required"))?;
+    if content_length > MAX_PUT_SIZE_BYTES {
+        return Err(actix_web::error::ErrorPayloadTooLarge(format!(
+            "Objects can be at most {} bytes",
+            MAX_PUT_SIZE_BYTES
+        )));
+    }
+
+    let etag = local_store
+        .write(&grant, body)
+        .await
+        .map_err(|e| {
+            error!("Error writing object {}: {:?}", key, e);
+            actix_web::error::ErrorInternalServerError(e.to_string())
+        })?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Upload not found"))?;
+
+    Ok(HttpResponse::Ok().insert_header(("ETag", format!("\"{}\"", etag))).finish())
+}
+
+/// Streams the object from disk. A single byte range is answered with a 206, requests for several ranges
+/// get the whole object.
+#[get("/{key:.*}")]
+async fn get_object(
+    local_store: web::Data<Arc<LocalStore>>,
+    key: web::Path<String>,
+    query: web::Query<SignedUrlQuery>,
+    range: Option<web::Header<Range>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let key = key.into_inner();
+    local_store.verify(&query.token, &key, GrantMethod::Get).map_err(|e| {
+        warn!("Rejected download of {}: {:?}", key, e);
+        actix_web::error::ErrorForbidden("Invalid or expired download URL")
+    })?;
+
+    let (mut file, size_bytes) = local_store
+        .open(&key)
+        .await
+        .map_err(|e| {
+            error!("Error opening object {}: {:?}", key, e);
+            actix_web::error::ErrorInternalServerError(e.to_string())
+        })?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Object {} not found", key)))?;
+
+    let range = match range.map(web::Header::into_inner) {
+        Some(Range::Bytes(specs)) if specs.len() == 1 => {
+            let Some(range) = specs[0].to_satisfiable_range(size_bytes) else {
+                return Ok(HttpResponse::RangeNotSatisfiable()
+                    .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                        range: None,
+                        instance_length: Some(size_bytes),
+                    }))
+                    .finish());
+            };
+            Some(range)
+        }
+        _ => None,
+    };
+
+    let mut response = match range {
+        Some((start, end)) => {
+            file.seek(SeekFrom::Start(start)).await.map_err(|e| {
+                error!("Error reading object {}: {:?}", key, e);
+                actix_web::error::ErrorInternalServerError(e.to_string())
+            })?;
+            let mut response = HttpResponse::PartialContent();
+            response.insert_header(ContentRange(ContentRangeSpec::Bytes {
+                range: Some((start, end)),
+                instance_length: Some(size_bytes),
+            }));
+            response
+        }
+        None => HttpResponse::Ok(),
+    };
+    let length = range.map_or(size_bytes, |(start, end)| end - start + 1);
+
+    let content_type = if key.ends_with(".mp4") { "video/mp4" } else { "application/octet-stream" };
+    Ok(response
+        .content_type(content_type)
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .body(SizedStream::new(length, ReaderStream::new(file.take(length)))))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self as actix_test, TestRequest};
+    use actix_web::App;
+
+    use super::*;
+    use crate::storage::ObjectStore;
+
+    #[actix_web::test]
+    async fn objects_are_streamed_whole_or_by_range() {
+        let root = std::env::temp_dir().join(format!("echo-storage-{}", uuid::Uuid::new_v4()));
+        let local_store = Arc::new(LocalStore::new(root.clone(), "http://localhost:8000".to_string(), "secret"));
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(local_store.clone()))
+                .service(web::scope("/storage").service(put_object).service(get_object)),
+        )
+        .await;
+        let path = |url: String| url.trim_start_matches("http://localhost:8000").to_string();
+
+        let put_url = path(local_store.presign_put("session/1.mp4".to_string()).await.unwrap());
+        let put = TestRequest::put().uri(&put_url).set_payload("hello world").to_request();
+        assert_eq!(actix_test::call_service(&app, put).await.status(), StatusCode::OK);
+
+        let res = actix_test::call_service(&app, TestRequest::put().uri(&put_url).to_request()).await;
+        assert_eq!(res.status(), StatusCode::LENGTH_REQUIRED);
+        let too_large = TestRequest::put()
+            .uri(&put_url)
+            .insert_header((header::CONTENT_LENGTH, MAX_PUT_SIZE_BYTES + 1))
+            .to_request();
+        assert_eq!(actix_test::call_service(&app, too_large).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let get_url = path(local_store.presign_get("session/1.mp4".to_string()).await.unwrap());
+        let get = |range: Option<&str>| {
+            let req = TestRequest::get().uri(&get_url);
+            match range {
+                Some(range) => req.insert_header((header::RANGE, range)).to_request(),
+                None => req.to_request(),
+            }
+        };
+
+        let res = actix_test::call_service(&app, get(None)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "video/mp4");
+        assert_eq!(actix_test::read_body(res).await, "hello world");
+
+        let res = actix_test::call_service(&app, get(Some("bytes=6-"))).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 6-10/11");
+        assert_eq!(actix_test::read_body(res).await, "world");
+
+        let res = actix_test::call_service(&app, get(Some("bytes=0-0, 2-3"))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(actix_test::read_body(res).await, "hello world");
+
+        let res = actix_test::call_service(&app, get(Some("bytes=20-30"))).await;
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers().get(header::CONTENT_RANGE).unwrap(), "bytes */11");
+
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+}
@@ -0,0 +1,468 @@
+use actix_web::web::Bytes;
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::{Stream, StreamExt};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::fs::Metadata;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::storage::{
+    ObjectInfo, ObjectStore, ObjectSummary, UploadedPart, DOWNLOAD_URL_EXPIRY, PRESIGNED_URL_EXPIRY,
+};
+
+/// Path the signed URLs of the local store point at, served by `routes::storage`
+pub const LOCAL_STORAGE_PATH: &str = "/storage";
+
+/// Largest object or part a signed URL accepts, the most R2 takes in a single PUT
+pub const MAX_PUT_SIZE_BYTES: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Directories under the storage root
+const OBJECTS_DIR: &str = "objects";
+const MULTIPART_DIR: &str = "multipart";
+const TMP_DIR: &str = "tmp";
+
+/// File in the directory of a multipart upload holding the key of the object it uploads
+const UPLOAD_KEY_FILE: &str = "key";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GrantMethod {
+    Get,
+    Put,
+}
+
+/// What a signed URL of the local store allows, carried as a JWT in its `token` query parameter
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StorageGrant {
+    pub key: String,
+    pub method: GrantMethod,
+    /// Set when the URL uploads one part of a multipart upload
+    pub upload_id: Option<String>,
+    pub part_number: Option<i32>,
+    pub exp: usize,
+}
+
+/// Objects kept in a directory on this machine, for running echo and its tests without R2. Clients
+/// upload to and download from echo itself through signed URLs.
+pub struct LocalStore {
+    root: PathBuf,
+    public_url: String,
+    signing_key: String,
+}
+
+impl LocalStore {
+    /// `public_url` is where clients reach echo, signed URLs are signed with a key derived from `jwt_secret`
+    /// so they can't be used as bearer tokens or the other way around
+    pub fn new(root: PathBuf, public_url: String, jwt_secret: &str) -> Self {
+        LocalStore {
+            root,
+            public_url: public_url.trim_end_matches('/').to_string(),
+            signing_key: format!("storage:{}", jwt_secret),
+        }
+    }
+
+    fn signed_url(
+        &self,
+        object_key: String,
+        method: GrantMethod,
+        part: Option<(String, i32)>,
+        expiry: Duration,
+    ) -> Result<String> {
+        // Fail early instead of handing out a URL that can't be served
+        self.object_path(&object_key)?;
+
+        let (upload_id, part_number) = part.unzip();
+        let grant = StorageGrant {
+            key: object_key,
+            method,
+            upload_id,
+            part_number,
+            exp: Utc::now().timestamp() as usize + expiry.as_secs() as usize,
+        };
+        let token = encode(&Header::default(), &grant, &EncodingKey::from_secret(self.signing_key.as_ref()))?;
+
+        Ok(format!("{}{}/{}?token={}", self.public_url, LOCAL_STORAGE_PATH, grant.key, token))
+    }
+
+    /// Check the token of a signed URL against the key and method of the request
+    pub fn verify(&self, token: &str, object_key: &str, method: GrantMethod) -> Result<StorageGrant> {
+        let mut validation = Validation::default();
+        validation.set_required_spec_claims(&["exp"]);
+
+        let grant = decode::<StorageGrant>(token, &DecodingKey::from_secret(self.signing_key.as_ref()), &validation)?
+            .claims;
+        if grant.key != object_key || grant.method != method {
+            bail!("token does not grant {:?} of {}", method, object_key);
+        }
+
+        Ok(grant)
+    }
+
+    /// Keys are relative paths, anything that could leave the objects directory is rejected
+    fn object_path(&self, object_key: &str) -> Result<PathBuf> {
+        let valid = object_key
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "." && segment != ".." && !segment.contains('\\'));
+        if !valid {
+            bail!("invalid object key {:?}", object_key);
+        }
+
+        Ok(self.root.join(OBJECTS_DIR).join(object_key))
+    }
+
+    /// Directory of a multipart upload of `object_key`, an error if there is no such upload
+    async fn upload_dir(&self, object_key: &str, upload_id: &str) -> Result<PathBuf> {
+        let upload_id = Uuid::parse_str(upload_id).map_err(|_| anyhow!("no such upload {}", upload_id))?;
+        let dir = self.root.join(MULTIPART_DIR).join(upload_id.to_string());
+
+        match tokio::fs::read_to_string(dir.join(UPLOAD_KEY_FILE)).await {
+            Ok(key) if key == object_key => Ok(dir),
+            Ok(_) => bail!("no such upload {} of {}", upload_id, object_key),
+            Err(e) if e.kind() == ErrorKind::NotFound => bail!("no such upload {}", upload_id),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the body of a PUT to a signed URL, returning the ETag of what was written. `None` if the
+    /// grant is for a part of a multipart upload that no longer exists.
+    pub async fn write<S, E>(&self, grant: &StorageGrant, mut body: S) -> Result<Option<String>>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let path = match (&grant.upload_id, grant.part_number) {
+            (Some(upload_id), Some(part_number)) => match self.upload_dir(&grant.key, upload_id).await {
+                Ok(dir) => dir.join(format!("part-{}", part_number)),
+                Err(_) => return Ok(None),
+            },
+            _ => self.object_path(&grant.key)?,
+        };
+
+        // Written next to the objects and moved into place, so a dropped upload never leaves half an object
+        let tmp_dir = self.root.join(TMP_DIR);
+        tokio::fs::create_dir_all(&tmp_dir).await?;
+        let tmp_path = tmp_dir.join(Uuid::new_v4().to_string());
+
+        let written: Result<()> = async {
+            let mut file = tokio::fs::File::create(&tmp_path).await?;
+            while let Some(chunk) = body.next().await {
+                file.write_all(&chunk?).await?;
+            }
+            file.sync_all().await?;
+            Ok(())
+        }
+        .await;
+        if let Err(e) = written {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(e);
+        }
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::rename(&tmp_path, &path).await?;
+
+        Ok(Some(etag(&tokio::fs::metadata(&path).await?)))
+    }
+
+    /// An object opened for reading and its size in bytes, `None` if it does not exist
+    pub async fn open(&self, object_key: &str) -> Result<Option<(tokio::fs::File, u64)>> {
+        let file = match tokio::fs::File::open(self.object_path(object_key)?).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let size_bytes = file.metadata().await?.len();
+
+        Ok(Some((file, size_bytes)))
+    }
+}
+
+/// Changes whenever the file is rewritten, like the ETags of most file servers
+fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    format!("{:x}-{:x}", modified.as_nanos(), metadata.len())
+}
+
+impl ObjectStore for LocalStore {
+    fn presign_put(&self, object_key: String) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move { self.signed_url(object_key, GrantMethod::Put, None, PRESIGNED_URL_EXPIRY) })
+    }
+
+    fn presign_get(&self, object_key: String) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move { self.signed_url(object_key, GrantMethod::Get, None, DOWNLOAD_URL_EXPIRY) })
+    }
+
+    fn head(&self, object_key: String) -> BoxFuture<'_, Result<Option<ObjectInfo>>> {
+        Box::pin(async move {
+            match tokio::fs::metadata(self.object_path(&object_key)?).await {
+                Ok(metadata) if metadata.is_file() => Ok(Some(ObjectInfo {
+                    size_bytes: metadata.len() as i64,
+                    etag: Some(etag(&metadata)),
+                })),
+                Ok(_) => Ok(None),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn delete(&self, object_key: String) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.object_path(&object_key)?).await {
+                Ok(()) => Ok(()),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn list(&self, prefix: String) -> BoxFuture<'_, Result<Vec<ObjectSummary>>> {
+        Box::pin(async move {
+            let objects_dir = self.root.join(OBJECTS_DIR);
+            let mut objects = Vec::new();
+            let mut dirs = vec![objects_dir.clone()];
+
+            while let Some(dir) = dirs.pop() {
+                let mut entries = match tokio::fs::read_dir(&dir).await {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                };
+
+                while let Some(entry) = entries.next_entry().await? {
+                    let metadata = entry.metadata().await?;
+                    if metadata.is_dir() {
+                        dirs.push(entry.path());
+                        continue;
+                    }
+
+                    let key = entry
+                        .path()
+                        .strip_prefix(&objects_dir)?
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    if key.starts_with(&prefix) {
+                        objects.push(ObjectSummary {
+                            key,
+                            size_bytes: metadata.len() as i64,
+                        });
+                    }
+                }
+            }
+
+            objects.sort_by(|a, b| a.key.cmp(&b.key));
+            Ok(objects)
+        })
+    }
+
+    fn create_multipart_upload(&self, object_key: String) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move {
+            self.object_path(&object_key)?;
+
+            let upload_id = Uuid::new_v4().to_string();
+            let dir = self.root.join(MULTIPART_DIR).join(&upload_id);
+            tokio::fs::create_dir_all(&dir).await?;
+            tokio::fs::write(dir.join(UPLOAD_KEY_FILE), &object_key).await?;
+
+            Ok(upload_id)
+        })
+    }
+
+    fn presign_upload_part(
+        &self,
+        object_key: String,
+        upload_id: String,
+        part_number: i32,
+    ) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move {
+            self.signed_url(
+                object_key,
+                GrantMethod::Put,
+                Some((upload_id, part_number)),
+                PRESIGNED_URL_EXPIRY,
+            )
+        })
+    }
+
+    fn list_parts(&self, object_key: String, upload_id: String) -> BoxFuture<'_, Result<Vec<UploadedPart>>> {
+        Box::pin(async move {
+            let dir = self.upload_dir(&object_key, &upload_id).await?;
+            let mut parts = Vec::new();
+
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let part_number = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.strip_prefix("part-"))
+                    .and_then(|part_number| part_number.parse().ok());
+                let Some(part_number) = part_number else {
+                    continue;
+                };
+
+                let metadata = entry.metadata().await?;
+                parts.push(UploadedPart {
+                    part_number,
+                    etag: etag(&metadata),
+                    size_bytes: metadata.len() as i64,
+                    last_modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+                });
+            }
+
+            parts.sort_by_key(|part| part.part_number);
+            Ok(parts)
+        })
+    }
+
+    fn complete_multipart_upload(
+        &self,
+        object_key: String,
+        upload_id: String,
+        parts: Vec<(i32, String)>,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let dir = self.upload_dir(&object_key, &upload_id).await?;
+            let uploaded = self.list_parts(object_key.clone(), upload_id.clone()).await?;
+
+            for (part_number, etag) in &parts {
+                let matches = uploaded
+                    .iter()
+                    .any(|part| part.part_number == *part_number && part.etag == etag.trim_matches('"'));
+                if !matches {
+                    bail!("part {} of upload {} is missing or has another ETag", part_number, upload_id);
+                }
+            }
+
+            let tmp_dir = self.root.join(TMP_DIR);
+            tokio::fs::create_dir_all(&tmp_dir).await?;
+            let tmp_path = tmp_dir.join(Uuid::new_v4().to_string());
+            let mut file = tokio::fs::File::create(&tmp_path).await?;
+            for (part_number, _) in &parts {
+                let mut part = tokio::fs::File::open(dir.join(format!("part-{}", part_number))).await?;
+                tokio::io::copy(&mut part, &mut file).await?;
+            }
+            file.sync_all().await?;
+
+            let path = self.object_path(&object_key)?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(&tmp_path, &path).await?;
+            tokio::fs::remove_dir_all(&dir).await?;
+
+            Ok(())
+        })
+    }
+
+    fn abort_multipart_upload(&self, object_key: String, upload_id: String) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let Ok(dir) = self.upload_dir(&object_key, &upload_id).await else {
+                return Ok(());
+            };
+
+            match tokio::fs::remove_dir_all(&dir).await {
+                Ok(()) => Ok(()),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tokio::io::AsyncReadExt;
+
+    fn store() -> LocalStore {
+        let root = std::env::temp_dir().join(format!("echo-storage-{}", Uuid::new_v4()));
+        LocalStore::new(root, "http://localhost:8000/".to_string(), "secret")
+    }
+
+    fn body(chunks: &[&'static [u8]]) -> impl Stream<Item = Result<Bytes, Infallible>> + Unpin {
+        futures::stream::iter(chunks.iter().map(|chunk| Ok(Bytes::from_static(chunk))).collect::<Vec<_>>())
+    }
+
+    fn token(url: &str) -> &str {
+        url.split_once("?token=").unwrap().1
+    }
+
+    async fn read(store: &LocalStore, key: &str) -> Vec<u8> {
+        let (mut file, size_bytes) = store.open(key).await.unwrap().unwrap();
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).await.unwrap();
+        assert_eq!(contents.len() as u64, size_bytes);
+        contents
+    }
+
+    #[actix_web::test]
+    async fn objects_round_trip_through_signed_urls() {
+        let store = store();
+
+        let url = store.presign_put("session/1.mp4".to_string()).await.unwrap();
+        assert!(url.starts_with("http://localhost:8000/storage/session/1.mp4?token="));
+        assert!(store.verify(token(&url), "session/2.mp4", GrantMethod::Put).is_err());
+        assert!(store.verify(token(&url), "session/1.mp4", GrantMethod::Get).is_err());
+
+        let grant = store.verify(token(&url), "session/1.mp4", GrantMethod::Put).unwrap();
+        let etag = store.write(&grant, body(&[b"hello ", b"world"])).await.unwrap();
+
+        let object = store.head("session/1.mp4".to_string()).await.unwrap().unwrap();
+        assert_eq!(object.size_bytes, 11);
+        assert_eq!(object.etag, etag);
+        assert_eq!(read(&store, "session/1.mp4").await, b"hello world");
+        assert_eq!(
+            store.list("session/".to_string()).await.unwrap(),
+            vec![ObjectSummary { key: "session/1.mp4".to_string(), size_bytes: 11 }]
+        );
+
+        store.delete("session/1.mp4".to_string()).await.unwrap();
+        assert!(store.head("session/1.mp4".to_string()).await.unwrap().is_none());
+        assert!(store.open("session/1.mp4").await.unwrap().is_none());
+        assert!(store.presign_put("../escape.mp4".to_string()).await.is_err());
+
+        tokio::fs::remove_dir_all(&store.root).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn multipart_uploads_are_assembled_in_part_order() {
+        let store = store();
+        let key = "session/2.mp4".to_string();
+        let upload_id = store.create_multipart_upload(key.clone()).await.unwrap();
+
+        for (part_number, contents) in [(2, b"world".as_slice()), (1, b"hello ".as_slice())] {
+            let url = store.presign_upload_part(key.clone(), upload_id.clone(), part_number).await.unwrap();
+            let grant = store.verify(token(&url), &key, GrantMethod::Put).unwrap();
+            store.write(&grant, body(&[contents])).await.unwrap().unwrap();
+        }
+
+        let parts = store.list_parts(key.clone(), upload_id.clone()).await.unwrap();
+        assert_eq!(parts.iter().map(|part| part.part_number).collect::<Vec<_>>(), vec![1, 2]);
+
+        let wrong_etags = vec![(1, "nope".to_string()), (2, parts[1].etag.clone())];
+        assert!(store.complete_multipart_upload(key.clone(), upload_id.clone(), wrong_etags).await.is_err());
+
+        let completed = parts.iter().map(|part| (part.part_number, part.etag.clone())).collect();
+        store.complete_multipart_upload(key.clone(), upload_id.clone(), completed).await.unwrap();
+        assert_eq!(read(&store, &key).await, b"hello world");
+        assert!(store.list_parts(key.clone(), upload_id.clone()).await.is_err());
+        store.abort_multipart_upload(key, upload_id).await.unwrap();
+
+        tokio::fs::remove_dir_all(&store.root).await.unwrap();
+    }
+}
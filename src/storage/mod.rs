@@ -0,0 +1,89 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::time::Duration;
+
+pub mod local;
+pub mod s3;
+
+pub use local::LocalStore;
+pub use s3::S3Store;
+
+/// How long a presigned upload URL stays valid
+const PRESIGNED_URL_EXPIRY: Duration = Duration::from_secs(3000);
+
+/// How long a presigned download URL stays valid
+const DOWNLOAD_URL_EXPIRY: Duration = Duration::from_secs(900);
+
+/// Size and ETag of an uploaded object
+#[derive(Debug)]
+pub struct ObjectInfo {
+    pub size_bytes: i64,
+    pub etag: Option<String>,
+}
+
+/// An object returned by `ObjectStore::list`
+#[allow(dead_code)] // Part of the storage interface, not used by any route yet
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectSummary {
+    pub key: String,
+    pub size_bytes: i64,
+}
+
+/// A part of a multipart upload that the store already has
+#[derive(Clone, Debug, Serialize)]
+pub struct UploadedPart {
+    pub part_number: i32,
+    pub etag: String,
+    pub size_bytes: i64,
+    /// When the store received the part, if it says
+    #[serde(skip)]
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// Where recordings are kept. Clients never send video through the API, they PUT and GET presigned URLs
+/// handed out by the store instead.
+pub trait ObjectStore: Send + Sync {
+    /// URL the client can PUT a whole object to
+    fn presign_put(&self, object_key: String) -> BoxFuture<'_, Result<String>>;
+
+    /// URL the client can GET an object from
+    fn presign_get(&self, object_key: String) -> BoxFuture<'_, Result<String>>;
+
+    /// Size and ETag of an object, `None` if it does not exist
+    fn head(&self, object_key: String) -> BoxFuture<'_, Result<Option<ObjectInfo>>>;
+
+    /// Remove an object, succeeds if it does not exist
+    #[allow(dead_code)] // Part of the storage interface, not used by any route yet
+    fn delete(&self, object_key: String) -> BoxFuture<'_, Result<()>>;
+
+    /// Every object whose key starts with `prefix`, in key order
+    #[allow(dead_code)] // Part of the storage interface, not used by any route yet
+    fn list(&self, prefix: String) -> BoxFuture<'_, Result<Vec<ObjectSummary>>>;
+
+    /// Start a multipart upload, returning its upload id
+    fn create_multipart_upload(&self, object_key: String) -> BoxFuture<'_, Result<String>>;
+
+    /// URL the client can PUT one part of a multipart upload to
+    fn presign_upload_part(
+        &self,
+        object_key: String,
+        upload_id: String,
+        part_number: i32,
+    ) -> BoxFuture<'_, Result<String>>;
+
+    /// Every part uploaded so far, in part number order
+    fn list_parts(&self, object_key: String, upload_id: String) -> BoxFuture<'_, Result<Vec<UploadedPart>>>;
+
+    /// Assemble the object from `(part_number, etag)` pairs
+    fn complete_multipart_upload(
+        &self,
+        object_key: String,
+        upload_id: String,
+        parts: Vec<(i32, String)>,
+    ) -> BoxFuture<'_, Result<()>>;
+
+    /// Drop a multipart upload and the parts uploaded for it, succeeds if the upload is already gone
+    fn abort_multipart_upload(&self, object_key: String, upload_id: String) -> BoxFuture<'_, Result<()>>;
+}
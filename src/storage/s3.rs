@@ -0,0 +1,249 @@
+use anyhow::{anyhow, Result};
+use aws_config::{meta::region::RegionProviderChain, Region};
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::{config::Credentials, presigning::PresigningConfig, Client};
+use chrono::DateTime;
+use futures::future::BoxFuture;
+
+use crate::storage::{
+    ObjectInfo, ObjectStore, ObjectSummary, UploadedPart, DOWNLOAD_URL_EXPIRY, PRESIGNED_URL_EXPIRY,
+};
+
+/// An S3 compatible bucket, Cloudflare R2 in production
+pub struct S3Store {
+    client: Client,
+    bucket: String,
+}
+
+impl S3Store {
+    pub async fn new(access_key_id: String, secret_access_key: String, endpoint_url: String, bucket: String) -> Self {
+        let region_provider = RegionProviderChain::default_provider().or_else(Region::new("auto"));
+        let credentials = Credentials::new(access_key_id, secret_access_key, None, None, "env-credentials");
+
+        let config = aws_config::from_env()
+            .region(region_provider)
+            .credentials_provider(credentials)
+            .endpoint_url(endpoint_url)
+            .load()
+            .await;
+
+        S3Store {
+            client: Client::new(&config),
+            bucket,
+        }
+    }
+}
+
+impl ObjectStore for S3Store {
+    fn presign_put(&self, object_key: String) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move {
+            let presigned_request = self
+                .client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(object_key)
+                .presigned(PresigningConfig::expires_in(PRESIGNED_URL_EXPIRY)?)
+                .await?;
+
+            Ok(presigned_request.uri().to_string())
+        })
+    }
+
+    fn presign_get(&self, object_key: String) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move {
+            let presigned_request = self
+                .client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(object_key)
+                .presigned(PresigningConfig::expires_in(DOWNLOAD_URL_EXPIRY)?)
+                .await?;
+
+            Ok(presigned_request.uri().to_string())
+        })
+    }
+
+    fn head(&self, object_key: String) -> BoxFuture<'_, Result<Option<ObjectInfo>>> {
+        Box::pin(async move {
+            match self.client.head_object().bucket(&self.bucket).key(object_key).send().await {
+                Ok(output) => Ok(Some(ObjectInfo {
+                    size_bytes: output.content_length().unwrap_or(0),
+                    etag: output.e_tag().map(|etag| etag.trim_matches('"').to_string()),
+                })),
+                Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn delete(&self, object_key: String) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            // S3 does not fail deletes of missing objects
+            self.client
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(object_key)
+                .send()
+                .await?;
+
+            Ok(())
+        })
+    }
+
+    fn list(&self, prefix: String) -> BoxFuture<'_, Result<Vec<ObjectSummary>>> {
+        Box::pin(async move {
+            let mut objects = Vec::new();
+            let mut continuation_token = None;
+
+            loop {
+                let output = self
+                    .client
+                    .list_objects_v2()
+                    .bucket(&self.bucket)
+                    .prefix(prefix.clone())
+                    .set_continuation_token(continuation_token)
+                    .send()
+                    .await?;
+
+                objects.extend(output.contents().iter().filter_map(|object| {
+                    Some(ObjectSummary {
+                        key: object.key()?.to_string(),
+                        size_bytes: object.size().unwrap_or(0),
+                    })
+                }));
+
+                match (output.is_truncated(), output.next_continuation_token()) {
+                    (Some(true), Some(token)) => continuation_token = Some(token.to_string()),
+                    _ => break,
+                }
+            }
+
+            Ok(objects)
+        })
+    }
+
+    fn create_multipart_upload(&self, object_key: String) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move {
+            let output = self
+                .client
+                .create_multipart_upload()
+                .bucket(&self.bucket)
+                .key(object_key)
+                .content_type("video/mp4")
+                .send()
+                .await?;
+
+            output
+                .upload_id()
+                .map(|upload_id| upload_id.to_string())
+                .ok_or_else(|| anyhow!("R2 did not return an upload id"))
+        })
+    }
+
+    fn presign_upload_part(
+        &self,
+        object_key: String,
+        upload_id: String,
+        part_number: i32,
+    ) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move {
+            let presigned_request = self
+                .client
+                .upload_part()
+                .bucket(&self.bucket)
+                .key(object_key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .presigned(PresigningConfig::expires_in(PRESIGNED_URL_EXPIRY)?)
+                .await?;
+
+            Ok(presigned_request.uri().to_string())
+        })
+    }
+
+    fn list_parts(&self, object_key: String, upload_id: String) -> BoxFuture<'_, Result<Vec<UploadedPart>>> {
+        Box::pin(async move {
+            let mut parts = Vec::new();
+            let mut part_number_marker = None;
+
+            loop {
+                let output = self
+                    .client
+                    .list_parts()
+                    .bucket(&self.bucket)
+                    .key(object_key.clone())
+                    .upload_id(upload_id.clone())
+                    .set_part_number_marker(part_number_marker)
+                    .send()
+                    .await?;
+
+                parts.extend(output.parts().iter().filter_map(|part| {
+                    Some(UploadedPart {
+                        part_number: part.part_number()?,
+                        etag: part.e_tag()?.trim_matches('"').to_string(),
+                        size_bytes: part.size().unwrap_or(0),
+                        last_modified: part
+                            .last_modified()
+                            .and_then(|modified| DateTime::from_timestamp(modified.secs(), modified.subsec_nanos())),
+                    })
+                }));
+
+                match (output.is_truncated(), output.next_part_number_marker()) {
+                    (Some(true), Some(marker)) => part_number_marker = Some(marker.to_string()),
+                    _ => break,
+                }
+            }
+
+            parts.sort_by_key(|part| part.part_number);
+            Ok(parts)
+        })
+    }
+
+    fn complete_multipart_upload(
+        &self,
+        object_key: String,
+        upload_id: String,
+        parts: Vec<(i32, String)>,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let completed_parts = parts
+                .iter()
+                .map(|(part_number, etag)| {
+                    CompletedPart::builder()
+                        .part_number(*part_number)
+                        .e_tag(format!("\"{}\"", etag.trim_matches('"')))
+                        .build()
+                })
+                .collect();
+
+            self.client
+                .complete_multipart_upload()
+                .bucket(&self.bucket)
+                .key(object_key)
+                .upload_id(upload_id)
+                .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(completed_parts)).build())
+                .send()
+                .await?;
+
+            Ok(())
+        })
+    }
+
+    fn abort_multipart_upload(&self, object_key: String, upload_id: String) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            match self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(object_key)
+                .upload_id(upload_id)
+                .send()
+                .await
+            {
+                Ok(_) => Ok(()),
+                Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_upload()) => Ok(()),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+}
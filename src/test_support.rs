@@ -0,0 +1,95 @@
+//! Shared setup for route tests. Tests that need Postgres run against the `DATABASE_URL` database, with
+//! every migration applied. They are `#[ignore]`d so a plain `cargo test` reports them as skipped, run them
+//! with `cargo test -- --include-ignored`.
+
+use actix_web::dev::ServiceRequest;
+use actix_web::HttpMessage;
+use shuttle_persist::PersistInstance;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+use crate::middleware::auth::{AuthenticatedUser, AuthorizedUser};
+use crate::models::users::{Permission, Role};
+use crate::storage::{LocalStore, ObjectStore};
+use crate::AppState;
+
+/// The test database, panics when `DATABASE_URL` is not set so an unconfigured run fails instead of passing
+pub async fn database() -> PgPool {
+    match std::env::var("DATABASE_URL") {
+        Ok(url) if !url.is_empty() => PgPool::connect(&url).await.expect("DATABASE_URL is not reachable"),
+        _ => panic!("DATABASE_URL must be set to run the tests that need Postgres"),
+    }
+}
+
+/// A pool that never connects, for tests whose requests are turned away before reaching the database
+pub fn unreachable_pool() -> PgPool {
+    PgPoolOptions::new()
+        .acquire_timeout(std::time::Duration::from_millis(100))
+        .connect_lazy("postgres://localhost:1/unreachable")
+        .unwrap()
+}
+
+pub fn app_state(pool: PgPool) -> Arc<AppState> {
+    let dir = std::env::temp_dir().join(format!("echo-test-{}", uuid::Uuid::new_v4()));
+    let object_store = LocalStore::new(dir.join("storage"), "http://localhost:8000".to_string(), "secret");
+
+    app_state_with_store(pool, Arc::new(object_store))
+}
+
+/// `app_state` with recordings kept in `object_store` instead of a temporary `LocalStore`
+pub fn app_state_with_store(pool: PgPool, object_store: Arc<dyn ObjectStore>) -> Arc<AppState> {
+    let dir = std::env::temp_dir().join(format!("echo-test-{}", uuid::Uuid::new_v4()));
+
+    Arc::new(AppState {
+        persist: PersistInstance::new(dir.join("persist")).unwrap(),
+        pool,
+        object_store,
+    })
+}
+
+pub fn authorized_user(user_id: &str, roles: &[Role], permissions: &[Permission]) -> AuthorizedUser {
+    AuthorizedUser {
+        user_id: user_id.to_string(),
+        roles: roles.to_vec(),
+        permissions: permissions.to_vec(),
+    }
+}
+
+/// Sign a request in as `authorized_user` without looking up their roles, call it from `App::wrap_fn` in
+/// place of `AuthenticationMiddleware`
+pub fn sign_in(req: &ServiceRequest, authorized_user: &AuthorizedUser) {
+    req.extensions_mut().insert(AuthenticatedUser {
+        user_id: authorized_user.user_id.clone(),
+    });
+    req.extensions_mut().insert(authorized_user.clone());
+}
+
+/// Sign a request in as `user_id`, leaving `AuthorizedUser` to load their roles from the database
+pub fn sign_in_as(req: &ServiceRequest, user_id: &str) {
+    req.extensions_mut().insert(AuthenticatedUser {
+        user_id: user_id.to_string(),
+    });
+}
+
+/// Initialise `services` with `app_state` and an `AppConfig::for_tests`, every request signed in as the
+/// `AuthorizedUser` `user`. A macro since the initialised service's request type can't be named here.
+macro_rules! signed_in_app {
+    ($app_state:expr, $user:expr, $($service:expr),+ $(,)?) => {{
+        let user: $crate::middleware::auth::AuthorizedUser = $user;
+        actix_web::test::init_service(
+            actix_web::App::new()
+                .wrap_fn(move |req, srv| {
+                    $crate::test_support::sign_in(&req, &user);
+                    actix_web::dev::Service::call(srv, req)
+                })
+                .app_data(actix_web::web::Data::new($app_state))
+                .app_data(actix_web::web::Data::new(std::sync::Arc::new(
+                    $crate::config::AppConfig::for_tests("secret"),
+                )))
+                $(.service($service))+,
+        )
+        .await
+    }};
+}
+pub(crate) use signed_in_app;
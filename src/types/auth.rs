@@ -42,6 +42,7 @@ pub struct WorkOSAuthResponse {
     pub organization_id: Option<String>,
 }
 
+#[allow(dead_code)] // Payload of the WorkOS user.created webhook, not handled yet
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WorkOSCreateUserWebhookPayload {
     pub id: String,
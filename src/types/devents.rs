@@ -1,20 +1,171 @@
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::models::devents::{KeyboardAction, MouseAction, ScrollAction};
+use crate::models::devents::{KeyboardAction, MouseAction, MousePhase, ScrollAction, WindowAction};
+use crate::models::{AppContext, ClockSkew, Devent, SequenceReport};
+use crate::types::ClientClock;
 
 #[derive(Deserialize)]
 pub struct DeventRequest {
     pub session_id: Uuid,
+    /// Unique per session, set it to make retrying a batch safe
+    pub client_event_id: Option<Uuid>,
+    /// Starts at 1 and increases by one per event for the lifetime of the session, lets the server spot
+    /// events the client dropped or sent out of order
+    pub seq: Option<i64>,
+    /// Button of a mouse event
     pub mouse_action: Option<MouseAction>,
+    /// Defaults to `click` when only `mouse_action` is set, like older clients send
+    pub mouse_phase: Option<MousePhase>,
+    /// Defaults to 1 for presses, releases and clicks and 2 for double clicks
+    pub click_count: Option<i32>,
     pub keyboard_action: Option<KeyboardAction>,
     pub scroll_action: Option<ScrollAction>,
+    /// Set on the event sent when the window in `app` gains or loses focus
+    pub window_action: Option<WindowAction>,
+    /// Application and window that received the event
+    pub app: Option<AppContextRequest>,
     pub mouse_x: i32,
     pub mouse_y: i32,
+    /// Leave unset on single display setups, the primary display is assumed
+    pub display_id: Option<String>,
     pub event_timestamp_nanos: i64,
+    /// Monotonic clock when the event happened, used with the `clock` of the batch to correct for a
+    /// wall clock that was stepped
+    pub monotonic_nanos: Option<i64>,
+}
+
+impl DeventRequest {
+    /// Phase of a mouse event, filling in the default for older clients
+    pub fn mouse_phase(&self) -> Option<MousePhase> {
+        self.mouse_phase
+            .or_else(|| self.mouse_action.as_ref().map(|_| MousePhase::Click))
+    }
+
+    pub fn app_context(&self) -> Option<AppContext> {
+        self.app.as_ref().map(|app| {
+            AppContext::new(app.bundle_id.clone(), app.app_name.clone(), app.window_title.clone())
+        })
+    }
+
+    /// Build the devent to store, with its timestamp on the server clock when the client clock is known
+    pub fn to_devent(&self, user_id: String, clock_skew: Option<&ClockSkew>) -> Devent {
+        let mouse_phase = self.mouse_phase();
+
+        Devent {
+            normalized_timestamp_nanos: clock_skew
+                .map(|clock_skew| clock_skew.normalize(self.event_timestamp_nanos, self.monotonic_nanos)),
+            client_event_id: self.client_event_id,
+            seq: self.seq,
+            window_action: self.window_action,
+            app_context_id: self.app_context().map(|app_context| app_context.id),
+            display_id: self.display_id.clone(),
+            mouse_phase,
+            click_count: self
+                .click_count
+                .or_else(|| mouse_phase.and_then(MousePhase::default_click_count)),
+            ..Devent::prepare_for_insert(
+                self.session_id,
+                user_id,
+                self.mouse_action.clone(),
+                self.keyboard_action.clone(),
+                self.scroll_action.clone(),
+                self.mouse_x,
+                self.mouse_y,
+                self.event_timestamp_nanos,
+            )
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AppContextRequest {
+    pub bundle_id: String,
+    pub app_name: Option<String>,
+    pub window_title: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct DeventRequestWrapper {
-    pub events: Vec<DeventRequest>
-}
\ No newline at end of file
+    /// Kept as raw JSON so one malformed event is rejected on its own instead of failing the batch
+    pub events: Vec<serde_json::Value>,
+    /// Client clock when the batch was sent, lets the server store normalized event timestamps
+    pub clock: Option<ClientClock>,
+}
+
+/// An event of a batch that was not stored, `index` is its position in the request
+#[derive(Clone, Debug, Serialize)]
+pub struct RejectedDevent {
+    pub index: usize,
+    pub reason: String,
+}
+
+#[derive(Serialize)]
+pub struct CreateDeventsResponse {
+    /// Devents stored by this request
+    pub accepted: u64,
+    /// Devents skipped because their `client_event_id` was already stored
+    pub duplicates: u64,
+    /// Devents that were not stored, they are kept in the `devent_rejections` table
+    pub rejected: Vec<RejectedDevent>,
+    /// Sessions whose `seq` numbers skip, repeat or go backwards in this request, numbers at or below
+    /// the highest one stored before the request are not checked
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub sequence_issues: HashMap<Uuid, SequenceReport>,
+}
+
+#[derive(Deserialize)]
+pub struct DeventPageQuery {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Only return devents received by the application with this bundle id
+    pub app: Option<String>,
+    /// Also return the coordinates relative to the display of each event
+    #[serde(default)]
+    pub normalize: bool,
+}
+
+/// A devent of a listing, with its position on its display when `normalize=true` was asked for
+#[derive(Serialize)]
+pub struct PositionedDevent<T> {
+    #[serde(flatten)]
+    pub devent: T,
+    /// From 0 at the left edge to 1 at the right edge of the display, unset if the display is unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalized_x: Option<f64>,
+    /// From 0 at the top edge to 1 at the bottom edge of the display, unset if the display is unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalized_y: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct DeventPage<T> {
+    pub data: Vec<T>,
+    /// Pass back as `cursor` to get the next page, `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// A single devent sent over the WebSocket ingestion channel. `seq` starts at 1 and increases by one
+/// per frame for the lifetime of the session, across reconnects. It is the devent's sequence number,
+/// `event.seq` can be left out and must match it when set.
+#[derive(Deserialize)]
+pub struct DeventStreamFrame {
+    pub seq: i64,
+    pub event: DeventRequest,
+    /// Client clock when the frame was sent, applies to this and the following frames until the next one
+    pub clock: Option<ClientClock>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DeventStreamMessage {
+    /// Sent on connect, the client should resume sending from `last_seq + 1`
+    Ready { last_seq: i64 },
+    /// Every frame up to and including `seq` has been persisted
+    Ack { seq: i64 },
+    Error { seq: Option<i64>, message: String },
+}
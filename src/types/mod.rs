@@ -1,7 +1,13 @@
 mod devents;
 mod recordings;
 mod auth;
+mod sessions;
+mod storage;
+mod users;
 
 pub use auth::*;
 pub use devents::*;
-pub use recordings::*;
\ No newline at end of file
+pub use recordings::*;
+pub use sessions::*;
+pub use storage::*;
+pub use users::*;
\ No newline at end of file
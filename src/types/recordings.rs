@@ -1,10 +1,39 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::types::ClientClock;
+
 #[derive(Deserialize)]
 pub struct SaveRecordingRequest {
     pub recording_id: Uuid,
     pub session_id: Uuid,
     pub start_timestamp_nanos: i64,
     pub duration_ms: u64,
-}
\ No newline at end of file
+    /// Monotonic clock when the recording started, see `ClientClock`
+    pub start_monotonic_nanos: Option<i64>,
+    /// Client clock when the request was sent, lets the server store a normalized start timestamp
+    pub clock: Option<ClientClock>,
+}
+
+/// Sent once the PUT to the upload URL succeeded
+#[derive(Deserialize)]
+pub struct CompleteRecordingRequest {
+    pub size_bytes: i64,
+    /// ETag returned by the PUT, checked as well when set
+    pub etag: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CreateMultipartUploadResponse {
+    pub recording_id: Uuid,
+    /// Parts are numbered from 1 up to this
+    pub max_part_number: i32,
+    /// Smallest size of every part but the last
+    pub min_part_size_bytes: i64,
+}
+
+#[derive(Deserialize)]
+pub struct CompleteMultipartUploadRequest {
+    /// Number of parts the recording was split into
+    pub part_count: i32,
+}
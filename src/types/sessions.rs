@@ -0,0 +1,82 @@
+use chrono::Utc;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::models::{ClockSkew, SessionDisplay};
+
+#[derive(Deserialize)]
+pub struct CreateSessionRequest {
+    /// The desktop client already generates session ids, so let it keep doing that
+    pub session_id: Option<Uuid>,
+    pub app_version: Option<String>,
+    pub os: Option<String>,
+    pub device_id: Option<String>,
+    pub screen_width: Option<i32>,
+    pub screen_height: Option<i32>,
+    pub start_timestamp_nanos: Option<i64>,
+    /// Displays attached when the session started
+    #[serde(default)]
+    pub displays: Vec<DisplayRequest>,
+}
+
+/// A display as the client reports it, see `SessionDisplay`
+#[derive(Deserialize)]
+pub struct DisplayRequest {
+    pub display_id: String,
+    #[serde(default)]
+    pub is_primary: bool,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub scale_factor: f64,
+}
+
+impl DisplayRequest {
+    pub fn to_session_display(&self, session_id: Uuid) -> SessionDisplay {
+        SessionDisplay {
+            session_id,
+            display_id: self.display_id.clone(),
+            is_primary: self.is_primary,
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+            scale_factor: self.scale_factor,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+/// Clock readings the client takes right before sending a request, so the server can work out how
+/// far the client clock is off
+#[derive(Clone, Deserialize)]
+pub struct ClientClock {
+    /// Wall clock, the same clock event and recording timestamps are taken from
+    pub timestamp_nanos: i64,
+    /// Monotonic clock, set it on events as well to survive the wall clock being stepped
+    pub monotonic_nanos: Option<i64>,
+}
+
+impl ClientClock {
+    pub fn to_clock_skew(&self, session_id: Uuid, user_id: String, received_at_nanos: i64) -> ClockSkew {
+        ClockSkew::new(
+            session_id,
+            user_id,
+            self.timestamp_nanos,
+            self.monotonic_nanos,
+            received_at_nanos,
+        )
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateDisplaysRequest {
+    pub displays: Vec<DisplayRequest>,
+}
+
+#[derive(Deserialize)]
+pub struct EndSessionRequest {
+    pub end_timestamp_nanos: Option<i64>,
+}
@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+/// Query string of a signed URL handed out by the local storage backend
+#[derive(Deserialize)]
+pub struct SignedUrlQuery {
+    pub token: String,
+}
@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::users::{Permission, Role};
+
+#[derive(Deserialize)]
+pub struct GrantRoleRequest {
+    pub role: Role,
+}
+
+#[derive(Serialize)]
+pub struct UserRolesResponse {
+    pub user_id: String,
+    pub roles: Vec<Role>,
+    pub permissions: Vec<Permission>,
+}
@@ -0,0 +1,373 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::fmt;
+
+use crate::models::devents::MousePhase;
+use crate::types::DeventRequest;
+
+/// Longest text a single key press may produce, IME commits can be several characters
+const MAX_KEY_CHARACTER_LEN: usize = 64;
+
+/// Longest bundle id, app name or window title we store
+const MAX_APP_FIELD_LEN: usize = 1024;
+
+/// Limits a `DeventRequest` has to stay within to be stored
+#[derive(Clone, Debug)]
+pub struct DeventValidationRules {
+    /// How far in the past an event may be, clients buffer events while offline
+    pub max_event_age: Duration,
+    /// How far ahead of the server clock an event may be
+    pub max_future_skew: Duration,
+    /// Largest absolute mouse coordinate or scroll delta, in pixels
+    pub max_coordinate: i32,
+    /// Longest key press or scroll, in milliseconds
+    pub max_action_duration_ms: i32,
+}
+
+impl Default for DeventValidationRules {
+    fn default() -> Self {
+        DeventValidationRules {
+            max_event_age: Duration::days(30),
+            max_future_skew: Duration::hours(1),
+            max_coordinate: 100_000,
+            max_action_duration_ms: 10 * 60 * 1000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeventViolation {
+    NoAction,
+    MultipleActions,
+    MissingMouseButton { phase: MousePhase },
+    UnexpectedMouseButton,
+    InvalidClickCount { phase: Option<MousePhase>, click_count: i32 },
+    CharacterTooLong { max: usize },
+    MissingAppContext,
+    InvalidAppField { field: &'static str },
+    NegativeDuration { action: &'static str },
+    DurationTooLong { action: &'static str, max_ms: i32 },
+    CoordinateOutOfRange { field: &'static str, value: i32, max: i32 },
+    InvalidSequence { seq: i64 },
+    TimestampTooOld { timestamp: DateTime<Utc> },
+    TimestampInFuture { timestamp: DateTime<Utc> },
+}
+
+impl fmt::Display for DeventViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeventViolation::NoAction => write!(f, "event has no mouse, keyboard, scroll or window action"),
+            DeventViolation::MultipleActions => {
+                write!(f, "event has more than one of mouse, keyboard, scroll and window action")
+            }
+            DeventViolation::MissingMouseButton { phase } => write!(f, "mouse {} event has no mouse_action", phase),
+            DeventViolation::UnexpectedMouseButton => write!(f, "mouse move event has a mouse_action, send a drag instead"),
+            DeventViolation::InvalidClickCount { phase: Some(phase), click_count } => {
+                write!(f, "click count of {} is not valid for a mouse {} event", click_count, phase)
+            }
+            DeventViolation::InvalidClickCount { phase: None, click_count } => {
+                write!(f, "click count of {} is set on an event that is not a mouse event", click_count)
+            }
+            DeventViolation::MissingAppContext => write!(f, "window event has no app"),
+            DeventViolation::InvalidAppField { field } => {
+                write!(f, "app {} must be between 1 and {} characters", field, MAX_APP_FIELD_LEN)
+            }
+            DeventViolation::CharacterTooLong { max } => write!(f, "keyboard character is longer than {} characters", max),
+            DeventViolation::NegativeDuration { action } => write!(f, "{} duration is negative", action),
+            DeventViolation::DurationTooLong { action, max_ms } => {
+                write!(f, "{} duration is longer than {}ms", action, max_ms)
+            }
+            DeventViolation::CoordinateOutOfRange { field, value, max } => {
+                write!(f, "{} of {} is outside of [-{}, {}]", field, value, max, max)
+            }
+            DeventViolation::InvalidSequence { seq } => write!(f, "seq {} is not positive", seq),
+            DeventViolation::TimestampTooOld { timestamp } => write!(f, "event timestamp {} is too far in the past", timestamp),
+            DeventViolation::TimestampInFuture { timestamp } => write!(f, "event timestamp {} is in the future", timestamp),
+        }
+    }
+}
+
+/// Check a single event, returning every rule it breaks
+pub fn validate_devent(
+    devent: &DeventRequest,
+    rules: &DeventValidationRules,
+    now: DateTime<Utc>,
+) -> Result<(), Vec<DeventViolation>> {
+    let mut violations = Vec::new();
+
+    let mouse_phase = devent.mouse_phase();
+    let action_count = [
+        mouse_phase.is_some(),
+        devent.keyboard_action.is_some(),
+        devent.scroll_action.is_some(),
+        devent.window_action.is_some(),
+    ]
+    .iter()
+    .filter(|is_set| **is_set)
+    .count();
+    match action_count {
+        0 => violations.push(DeventViolation::NoAction),
+        1 => {}
+        _ => violations.push(DeventViolation::MultipleActions),
+    }
+
+    match &devent.app {
+        Some(app) => {
+            let fields = [
+                ("bundle_id", Some(&app.bundle_id)),
+                ("app_name", app.app_name.as_ref()),
+                ("window_title", app.window_title.as_ref()),
+            ];
+            for (field, value) in fields {
+                if value.is_some_and(|value| value.is_empty() || value.chars().count() > MAX_APP_FIELD_LEN) {
+                    violations.push(DeventViolation::InvalidAppField { field });
+                }
+            }
+        }
+        None if devent.window_action.is_some() => violations.push(DeventViolation::MissingAppContext),
+        None => {}
+    }
+
+    if let Some(phase) = mouse_phase {
+        if phase.needs_button() && devent.mouse_action.is_none() {
+            violations.push(DeventViolation::MissingMouseButton { phase });
+        } else if !phase.needs_button() && devent.mouse_action.is_some() {
+            violations.push(DeventViolation::UnexpectedMouseButton);
+        }
+    }
+    if let Some(click_count) = devent.click_count {
+        // Moves and drags have no clicks, the rest need at least as many as their default
+        let min_click_count = mouse_phase.and_then(MousePhase::default_click_count);
+        if min_click_count.is_none_or(|min| click_count < min) {
+            violations.push(DeventViolation::InvalidClickCount {
+                phase: mouse_phase,
+                click_count,
+            });
+        }
+    }
+
+    let mut check_duration = |action: &'static str, duration: i32| {
+        if duration < 0 {
+            violations.push(DeventViolation::NegativeDuration { action });
+        } else if duration > rules.max_action_duration_ms {
+            violations.push(DeventViolation::DurationTooLong {
+                action,
+                max_ms: rules.max_action_duration_ms,
+            });
+        }
+    };
+    if let Some(keyboard_action) = &devent.keyboard_action {
+        check_duration("keyboard", keyboard_action.duration);
+    }
+    if let Some(scroll_action) = &devent.scroll_action {
+        check_duration("scroll", scroll_action.duration);
+    }
+
+    if let Some(character) = devent.keyboard_action.as_ref().and_then(|k| k.character.as_ref()) {
+        if character.chars().count() > MAX_KEY_CHARACTER_LEN {
+            violations.push(DeventViolation::CharacterTooLong { max: MAX_KEY_CHARACTER_LEN });
+        }
+    }
+
+    let mut coordinates = vec![("mouse_x", devent.mouse_x), ("mouse_y", devent.mouse_y)];
+    if let Some(scroll_action) = &devent.scroll_action {
+        coordinates.push(("scroll x", scroll_action.x));
+        coordinates.push(("scroll y", scroll_action.y));
+    }
+    for (field, value) in coordinates {
+        // i32::MIN has no positive counterpart, so compare without abs()
+        if value > rules.max_coordinate || value < -rules.max_coordinate {
+            violations.push(DeventViolation::CoordinateOutOfRange {
+                field,
+                value,
+                max: rules.max_coordinate,
+            });
+        }
+    }
+
+    if let Some(seq) = devent.seq.filter(|seq| *seq < 1) {
+        violations.push(DeventViolation::InvalidSequence { seq });
+    }
+
+    let timestamp = Utc.timestamp_nanos(devent.event_timestamp_nanos);
+    if timestamp < now - rules.max_event_age {
+        violations.push(DeventViolation::TimestampTooOld { timestamp });
+    } else if timestamp > now + rules.max_future_skew {
+        violations.push(DeventViolation::TimestampInFuture { timestamp });
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+/// Turn the violations of one event into the single reason reported back to the client
+pub fn describe_violations(violations: &[DeventViolation]) -> String {
+    violations
+        .iter()
+        .map(|violation| violation.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::models::devents::{KeyboardAction, KeyboardActionKey, MouseAction, ScrollAction, WindowAction};
+    use crate::types::AppContextRequest;
+
+    fn click(now: DateTime<Utc>) -> DeventRequest {
+        DeventRequest {
+            session_id: Uuid::new_v4(),
+            client_event_id: None,
+            seq: Some(1),
+            mouse_action: Some(MouseAction::Left),
+            mouse_phase: None,
+            click_count: None,
+            keyboard_action: None,
+            scroll_action: None,
+            window_action: None,
+            app: None,
+            mouse_x: 100,
+            mouse_y: 200,
+            display_id: None,
+            event_timestamp_nanos: now.timestamp_nanos_opt().unwrap(),
+            monotonic_nanos: None,
+        }
+    }
+
+    #[test]
+    fn valid_event_passes() {
+        let now = Utc::now();
+        assert_eq!(validate_devent(&click(now), &DeventValidationRules::default(), now), Ok(()));
+    }
+
+    #[test]
+    fn event_needs_exactly_one_action() {
+        let now = Utc::now();
+        let rules = DeventValidationRules::default();
+
+        let mut devent = click(now);
+        devent.mouse_action = None;
+        assert_eq!(validate_devent(&devent, &rules, now), Err(vec![DeventViolation::NoAction]));
+
+        let mut devent = click(now);
+        devent.keyboard_action = Some(KeyboardAction {
+            key: KeyboardActionKey::A,
+            duration: 10,
+            modifiers: vec![],
+            keycode: None,
+            character: None,
+        });
+        devent.scroll_action = Some(ScrollAction { x: 0, y: 10, duration: 10 });
+        assert_eq!(validate_devent(&devent, &rules, now), Err(vec![DeventViolation::MultipleActions]));
+    }
+
+    #[test]
+    fn mouse_phases_need_matching_buttons_and_click_counts() {
+        let now = Utc::now();
+        let rules = DeventValidationRules::default();
+
+        let mut devent = click(now);
+        devent.mouse_action = None;
+        devent.mouse_phase = Some(MousePhase::Move);
+        assert_eq!(validate_devent(&devent, &rules, now), Ok(()));
+
+        devent.mouse_phase = Some(MousePhase::Drag);
+        assert_eq!(
+            validate_devent(&devent, &rules, now),
+            Err(vec![DeventViolation::MissingMouseButton { phase: MousePhase::Drag }])
+        );
+
+        let mut devent = click(now);
+        devent.mouse_phase = Some(MousePhase::Double);
+        devent.click_count = Some(1);
+        assert_eq!(
+            validate_devent(&devent, &rules, now),
+            Err(vec![DeventViolation::InvalidClickCount {
+                phase: Some(MousePhase::Double),
+                click_count: 1,
+            }])
+        );
+
+        devent.click_count = Some(3);
+        assert_eq!(validate_devent(&devent, &rules, now), Ok(()));
+    }
+
+    #[test]
+    fn window_events_need_an_app() {
+        let now = Utc::now();
+        let rules = DeventValidationRules::default();
+
+        let mut devent = click(now);
+        devent.mouse_action = None;
+        devent.window_action = Some(WindowAction::Focus);
+        assert_eq!(validate_devent(&devent, &rules, now), Err(vec![DeventViolation::MissingAppContext]));
+
+        devent.app = Some(AppContextRequest {
+            bundle_id: "com.apple.Safari".to_string(),
+            app_name: Some("Safari".to_string()),
+            window_title: Some(String::new()),
+        });
+        assert_eq!(
+            validate_devent(&devent, &rules, now),
+            Err(vec![DeventViolation::InvalidAppField { field: "window_title" }])
+        );
+
+        devent.app.as_mut().unwrap().window_title = None;
+        assert_eq!(validate_devent(&devent, &rules, now), Ok(()));
+    }
+
+    #[test]
+    fn negative_durations_and_huge_coordinates_are_rejected() {
+        let now = Utc::now();
+        let mut devent = click(now);
+        devent.mouse_action = None;
+        devent.keyboard_action = Some(KeyboardAction {
+            key: KeyboardActionKey::A,
+            duration: -5,
+            modifiers: vec![],
+            keycode: None,
+            character: None,
+        });
+        devent.mouse_x = i32::MIN;
+
+        let violations = validate_devent(&devent, &DeventValidationRules::default(), now).unwrap_err();
+        assert_eq!(violations.len(), 2);
+        assert!(violations.contains(&DeventViolation::NegativeDuration { action: "keyboard" }));
+    }
+
+    #[test]
+    fn timestamps_must_be_near_now() {
+        let now = Utc::now();
+        let rules = DeventValidationRules::default();
+
+        let mut devent = click(now);
+        devent.event_timestamp_nanos = 0;
+        assert!(matches!(
+            validate_devent(&devent, &rules, now).unwrap_err()[..],
+            [DeventViolation::TimestampTooOld { .. }]
+        ));
+
+        devent.event_timestamp_nanos = i64::MAX;
+        assert!(matches!(
+            validate_devent(&devent, &rules, now).unwrap_err()[..],
+            [DeventViolation::TimestampInFuture { .. }]
+        ));
+    }
+
+    #[test]
+    fn violations_are_joined_into_one_reason() {
+        let now = Utc::now();
+        let mut devent = click(now);
+        devent.mouse_action = None;
+        devent.event_timestamp_nanos = 0;
+
+        let violations = validate_devent(&devent, &DeventValidationRules::default(), now).unwrap_err();
+        let reason = describe_violations(&violations);
+        assert!(reason.starts_with("event has no mouse, keyboard, scroll or window action; event timestamp"));
+    }
+}
@@ -0,0 +1,2 @@
+pub mod devents;
+pub mod sessions;
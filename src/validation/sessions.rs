@@ -0,0 +1,29 @@
+use std::collections::HashSet;
+
+use crate::types::DisplayRequest;
+
+/// Check the displays a client reports for a session, returning why they can't be stored
+pub fn validate_displays(displays: &[DisplayRequest]) -> Result<(), String> {
+    let mut display_ids = HashSet::new();
+
+    for display in displays {
+        if display.display_id.is_empty() {
+            return Err("display_id must not be empty".to_string());
+        }
+        if !display_ids.insert(display.display_id.as_str()) {
+            return Err(format!("display {} is listed more than once", display.display_id));
+        }
+        if display.width <= 0 || display.height <= 0 {
+            return Err(format!("display {} must have a positive width and height", display.display_id));
+        }
+        if !display.scale_factor.is_finite() || display.scale_factor <= 0.0 {
+            return Err(format!("display {} must have a positive scale_factor", display.display_id));
+        }
+    }
+
+    if displays.iter().filter(|display| display.is_primary).count() > 1 {
+        return Err("only one display can be primary".to_string());
+    }
+
+    Ok(())
+}